@@ -0,0 +1,55 @@
+use std::path::{Path, PathBuf};
+use std::ffi::OsString;
+use std::fs;
+use std::io;
+
+use chrono::{DateTime, Local, NaiveDateTime, TimeZone};
+
+/// How many backups are kept for sets which don't specify otherwise.
+pub const DEFAULT_BACKUP_COUNT: usize = 5;
+
+const TIMESTAMP_FORMAT: &str = "%Y%m%d-%H%M%S";
+const BACKUP_EXTENSION: &str = "bak";
+
+/// A copy of a set file made just before it was overwritten. Backups are kept next to the set and named
+/// after it, e.g. `walls.bgs.20191018-153000.bak`.
+pub struct Backup {
+    pub path: PathBuf,
+    pub time: DateTime<Local>,
+}
+
+/// Find the backups of the set file at `path`, newest first.
+pub fn list_backups(path: &Path) -> Vec<Backup> {
+    let (folder, prefix) = match (path.parent(), path.file_name()) {
+        (Some(folder), Some(name)) => (folder, format!("{}.", name.to_string_lossy())),
+        _ => return Vec::new(),
+    };
+    let suffix = format!(".{}", BACKUP_EXTENSION);
+    let folder = if folder.as_os_str().is_empty() { Path::new(".") } else { folder };
+
+    let mut backups: Vec<_> = fs::read_dir(folder).into_iter().flatten().filter_map(|entry| {
+        let entry = entry.ok()?;
+        let name = entry.file_name().into_string().ok()?;
+        if !name.starts_with(&prefix) || !name.ends_with(&suffix) { return None }
+        let timestamp = &name[prefix.len()..name.len() - suffix.len()];
+        let time = NaiveDateTime::parse_from_str(timestamp, TIMESTAMP_FORMAT).ok()?;
+        Some(Backup { path: entry.path(), time: Local.from_local_datetime(&time).earliest()? })
+    }).collect();
+    backups.sort_by(|a, b| b.time.cmp(&a.time));
+    backups
+}
+
+/// Copy the set file at `path`, if there is one, to a new backup, then delete the oldest backups so that
+/// only `keep` remain. If `keep` is 0, backups are turned off: none is made, and existing ones are left alone.
+pub(super) fn back_up(path: &Path, keep: usize) -> io::Result<()> {
+    if keep == 0 { return Ok(()) }
+    if path.is_file() {
+        let mut name = path.file_name().map(OsString::from).unwrap_or_default();
+        name.push(format!(".{}.{}", Local::now().format(TIMESTAMP_FORMAT), BACKUP_EXTENSION));
+        fs::copy(path, path.with_file_name(name))?;
+    }
+    for old in list_backups(path).into_iter().skip(keep) {
+        fs::remove_file(old.path)?;
+    }
+    Ok(())
+}
@@ -0,0 +1,186 @@
+use std::path::PathBuf;
+use std::collections::HashMap;
+use std::io;
+
+use crate::sources::{self, OriginalKey};
+use crate::background::{EditInfo, DesktopBackgroundFlags};
+use crate::background::persist::{SavedBackgroundSet, SavedDesktopBackground, describe_load_error};
+
+/// Something wrong with a saved set, found by `SavedBackgroundSet::check`. Sources and backgrounds are identified by
+/// their indices in the saved set.
+#[derive(Debug)]
+pub enum Problem {
+    /// The set has no image folder, or it doesn't exist.
+    MissingImageFolder { path: Option<PathBuf> },
+    UnloadableSource { source: usize, error: sources::SourceLoadError },
+    /// The background's key can't be read by its source, so its original can't be found.
+    CorruptOriginalKey { source: usize, background: usize, error: serde_json::Error },
+    /// Several backgrounds of the same source refer to the same original.
+    DuplicateKey { source: usize, backgrounds: Vec<usize> },
+    /// The background's crop region is impossible, e.g. its scale isn't positive or its center is outside the image.
+    InvalidEditInfo { source: usize, background: usize },
+}
+
+impl Problem {
+    /// The repairs which would fix this problem. The first one is the least destructive.
+    pub fn repairs(&self) -> Vec<Repair> {
+        match *self {
+            Problem::MissingImageFolder { path: Some(_) } => vec![Repair::CreateImageFolder],
+            Problem::MissingImageFolder { path: None } => vec![],
+            Problem::UnloadableSource { source, .. } => vec![Repair::RemoveSource { source }],
+            Problem::CorruptOriginalKey { source, background, .. } => vec![Repair::RemoveBackground { source, background }],
+            // Keep the first background, since there's no telling which of the others is better
+            Problem::DuplicateKey { source, ref backgrounds } => backgrounds[1..].iter()
+                .map(|&background| Repair::RemoveBackground { source, background }).collect(),
+            Problem::InvalidEditInfo { source, background } => vec![Repair::ResetEdits { source, background }],
+        }
+    }
+
+    /// A human-readable explanation of the problem.
+    pub fn describe(&self, set: &SavedBackgroundSet) -> String {
+        match self {
+            Problem::MissingImageFolder { path: Some(path) } => format!("The image folder {} doesn't exist.", path.to_string_lossy()),
+            Problem::MissingImageFolder { path: None } => "The set has no image folder.".to_owned(),
+            Problem::UnloadableSource { source, error } => format!("Source {} can't be loaded. {}",
+                set.describe_source(*source), describe_load_error(&set.sources[*source].ty, error)),
+            Problem::CorruptOriginalKey { source, background, error } => format!("The original of {} can't be found because its key is corrupt: {}",
+                set.describe_background(*source, *background), error),
+            Problem::DuplicateKey { source, backgrounds } => format!("{} backgrounds in source {} have the same original: {}",
+                backgrounds.len(), set.describe_source(*source),
+                backgrounds.iter().map(|&b| set.sources[*source].backgrounds[b].name.as_str()).collect::<Vec<_>>().join(", ")),
+            Problem::InvalidEditInfo { source, background } => format!("The crop region of {} is invalid.", set.describe_background(*source, *background)),
+        }
+    }
+}
+
+/// An automatic fix for a `Problem`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Repair {
+    CreateImageFolder,
+    /// Forget the background's crop region, so that it's treated as unedited.
+    ResetEdits { source: usize, background: usize },
+    RemoveBackground { source: usize, background: usize },
+    /// Remove the source and all of its backgrounds.
+    RemoveSource { source: usize },
+}
+
+impl Repair {
+    pub fn describe(&self, set: &SavedBackgroundSet) -> String {
+        match *self {
+            Repair::CreateImageFolder => "Create the image folder".to_owned(),
+            Repair::ResetEdits { source, background } => format!("Reset the crop region of {}", set.describe_background(source, background)),
+            Repair::RemoveBackground { source, background } => format!("Remove {}", set.describe_background(source, background)),
+            Repair::RemoveSource { source } => format!("Remove source {} and its {} backgrounds",
+                set.describe_source(source), set.sources[source].backgrounds.len()),
+        }
+    }
+
+    /// Whether the repair can be made without losing anything the user might want back.
+    pub fn is_safe(&self) -> bool {
+        match self {
+            Repair::CreateImageFolder | Repair::ResetEdits { .. } => true,
+            Repair::RemoveBackground { .. } | Repair::RemoveSource { .. } => false,
+        }
+    }
+}
+
+impl SavedBackgroundSet {
+    /// Look for problems in the set which loading it wouldn't reject outright.
+    pub fn check(&self) -> Vec<Problem> {
+        let mut problems = Vec::new();
+        match &self.image_folder {
+            Some(path) if path.is_dir() => {},
+            path => problems.push(Problem::MissingImageFolder { path: path.clone() }),
+        }
+        for (source_index, saved_source) in self.sources.iter().enumerate() {
+            for (index, background) in saved_source.backgrounds.iter().enumerate() {
+                if background.edit_info.as_ref().map_or(false, |e| !edit_info_valid(e, background)) {
+                    problems.push(Problem::InvalidEditInfo { source: source_index, background: index });
+                }
+            }
+
+            let source = match self.load_source(source_index) {
+                Ok(source) => source,
+                Err(error) => {
+                    problems.push(Problem::UnloadableSource { source: source_index, error });
+                    continue
+                }
+            };
+            let mut keys: HashMap<OriginalKey, Vec<usize>> = HashMap::new();
+            for (index, background) in saved_source.backgrounds.iter().enumerate() {
+                match source.check_key(&background.key_data) {
+                    // Only valid keys can be hashed
+                    Ok(()) => keys.entry(source.assemble_key(background.key_data.clone())).or_default().push(index),
+                    Err(error) => problems.push(Problem::CorruptOriginalKey { source: source_index, background: index, error }),
+                }
+            }
+            let mut duplicates: Vec<_> = keys.into_iter().map(|(_, backgrounds)| backgrounds).filter(|b| b.len() > 1).collect();
+            duplicates.sort();
+            problems.extend(duplicates.into_iter().map(|backgrounds| Problem::DuplicateKey { source: source_index, backgrounds }));
+        }
+        problems
+    }
+
+    /// Make the given repairs. Indices in the repairs refer to the set as it was checked, so every repair to be made
+    /// must be passed in one call.
+    pub fn repair(&mut self, repairs: &[Repair]) -> io::Result<()> {
+        let mut removed_backgrounds = Vec::new();
+        let mut removed_sources = Vec::new();
+        for repair in repairs {
+            match *repair {
+                Repair::CreateImageFolder => if let Some(path) = &self.image_folder {
+                    std::fs::create_dir_all(path)?;
+                },
+                Repair::ResetEdits { source, background } => {
+                    let background = &mut self.sources[source].backgrounds[background];
+                    background.edit_info = None;
+                    background.flags.insert(DesktopBackgroundFlags::UNEDITED);
+                },
+                Repair::RemoveBackground { source, background } => removed_backgrounds.push((source, background)),
+                Repair::RemoveSource { source } => removed_sources.push(source),
+            }
+        }
+        // Remove from the back so that the remaining indices stay correct
+        removed_backgrounds.sort();
+        removed_backgrounds.dedup();
+        for &(source, background) in removed_backgrounds.iter().rev() {
+            self.sources[source].backgrounds.remove(background);
+        }
+        removed_sources.sort();
+        removed_sources.dedup();
+        for &source in removed_sources.iter().rev() {
+            self.sources.remove(source);
+        }
+        Ok(())
+    }
+
+    fn load_source(&self, index: usize) -> Result<Box<dyn sources::ErasedDesktopBackgroundSource>, sources::SourceLoadError> {
+        let saved = &self.sources[index];
+        let supported = sources::source_version(&saved.ty);
+        match saved.version > supported {
+            true => Err(sources::SourceLoadError::NewerVersion { version: saved.version, supported }),
+            false => sources::load_source_by_id(&saved.ty, saved.data.clone()),
+        }
+    }
+
+    fn describe_source(&self, index: usize) -> String {
+        let saved = &self.sources[index];
+        match saved.data.get("name").and_then(serde_json::Value::as_str) {
+            Some(name) => format!("\"{}\"", name),
+            None => format!("#{} ({})", index + 1, saved.ty),
+        }
+    }
+
+    fn describe_background(&self, source: usize, background: usize) -> String {
+        format!("\"{}\" in source {}", self.sources[source].backgrounds[background].name, self.describe_source(source))
+    }
+}
+
+fn edit_info_valid(edit_info: &EditInfo, background: &SavedDesktopBackground) -> bool {
+    let EditInfo { center, scale } = *edit_info;
+    if !(scale.is_finite() && scale > 0.0 && center.x.is_finite() && center.y.is_finite()) { return false }
+    match background.original_meta.last_known_size {
+        Some((width, height)) => (0.0..=width as f32).contains(&center.x) && (0.0..=height as f32).contains(&center.y),
+        None => true, // Without a size there's nothing to check the center against
+    }
+}
@@ -0,0 +1,122 @@
+use std::io;
+
+use image::RgbaImage;
+use serde::{Serialize, Deserialize};
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub enum OutputFormat {
+    Png,
+    Jpeg,
+    WebP,
+}
+
+impl OutputFormat {
+    pub const ALL: [OutputFormat; 3] = [OutputFormat::Png, OutputFormat::Jpeg, OutputFormat::WebP];
+
+    pub fn name(self) -> &'static str {
+        match self {
+            OutputFormat::Png => "PNG",
+            OutputFormat::Jpeg => "JPEG",
+            OutputFormat::WebP => "WebP",
+        }
+    }
+
+    pub fn extension(self) -> &'static str {
+        match self {
+            OutputFormat::Png => "png",
+            OutputFormat::Jpeg => "jpg",
+            OutputFormat::WebP => "webp",
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub enum PngCompression {
+    Fast,
+    Default,
+    Best,
+}
+
+impl PngCompression {
+    pub const ALL: [PngCompression; 3] = [PngCompression::Fast, PngCompression::Default, PngCompression::Best];
+
+    pub fn name(self) -> &'static str {
+        match self {
+            PngCompression::Fast => "Fast",
+            PngCompression::Default => "Default",
+            PngCompression::Best => "Best",
+        }
+    }
+}
+
+/// How the image folder's outputs are encoded.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct OutputSettings {
+    pub format: OutputFormat,
+    /// The quality of JPEG and WebP outputs, from 1 to 100.
+    pub quality: u8,
+    pub png_compression: PngCompression,
+    /// Whether PNG outputs are losslessly optimised after encoding. This makes them smaller, but is slow.
+    pub optimize: bool,
+}
+
+impl Default for OutputSettings {
+    fn default() -> OutputSettings {
+        OutputSettings { format: OutputFormat::Png, quality: 90, png_compression: PngCompression::Default, optimize: false }
+    }
+}
+
+impl OutputSettings {
+    /// A short human-readable summary, e.g. "JPEG, quality 90".
+    pub fn describe(&self) -> String {
+        match self.format {
+            OutputFormat::Png if self.optimize => format!("PNG, {} compression, optimised", self.png_compression.name().to_lowercase()),
+            OutputFormat::Png => format!("PNG, {} compression", self.png_compression.name().to_lowercase()),
+            OutputFormat::Jpeg | OutputFormat::WebP => format!("{}, quality {}", self.format.name(), self.quality),
+        }
+    }
+
+    /// Identifies the settings which affect the output in the current format, so that changing the others doesn't
+    /// make the image folder need rebuilding.
+    pub(super) fn encoder_id(&self) -> String {
+        match self.format {
+            OutputFormat::Png => format!("png:{:?}:{}", self.png_compression, self.optimize),
+            OutputFormat::Jpeg => format!("jpeg:{}", self.quality),
+            OutputFormat::WebP => format!("webp:{}", self.quality),
+        }
+    }
+
+    pub(super) fn encode(&self, image: &RgbaImage) -> io::Result<Vec<u8>> {
+        let (width, height) = image.dimensions();
+        let mut data = Vec::new();
+        match self.format {
+            OutputFormat::Png => {
+                let mut encoder = png::Encoder::new(&mut data, width, height);
+                encoder.set_color(png::ColorType::RGBA);
+                encoder.set_depth(png::BitDepth::Eight);
+                encoder.set_compression(match self.png_compression {
+                    PngCompression::Fast => png::Compression::Fast,
+                    PngCompression::Default => png::Compression::Default,
+                    PngCompression::Best => png::Compression::Best,
+                });
+                encoder.write_header()?.write_image_data(image)?;
+                if self.optimize {
+                    data = oxipng::optimize_from_memory(&data, &oxipng::Options::default())
+                        .map_err(|e| io::Error::new(io::ErrorKind::Other, e.to_string()))?;
+                }
+            },
+            OutputFormat::Jpeg => {
+                // JPEG has no alpha channel
+                let rgb = image::DynamicImage::ImageRgba8(image.clone()).to_rgb();
+                image::jpeg::JPEGEncoder::new_with_quality(&mut data, self.quality.max(1).min(100))
+                    .encode(&rgb, width, height, image::ColorType::RGB(8))?;
+            },
+            OutputFormat::WebP => {
+                let encoded = webp::Encoder::from_rgba(image, width, height).encode(self.quality.max(1).min(100) as f32);
+                data.extend_from_slice(&encoded);
+            },
+        }
+        Ok(data)
+    }
+}
@@ -0,0 +1,149 @@
+use std::path::{Path, PathBuf};
+use std::time::SystemTime;
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, ErrorKind};
+
+use chrono::{DateTime, Local};
+use serde::{Serialize, Deserialize};
+use uuid::Uuid;
+
+use crate::background::persist::Error;
+
+/// How a set file should be opened, given that someone else may be editing it.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum OpenMode {
+    /// Lock the file, failing with `Error::Locked` if someone else holds the lock.
+    Edit,
+    /// Don't lock the file. The set will try to lock it when it's saved there.
+    ReadOnly,
+    /// Take the lock even if someone else holds it, e.g. because it was left behind by a crash.
+    BreakLock,
+}
+
+/// Who holds the lock on a set file, as recorded in the lock file.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct LockHolder {
+    pub host: String,
+    pub user: String,
+    pub pid: u32,
+    /// When the lock was taken.
+    pub time: SystemTime,
+    token: Uuid, // Tells apart locks taken by the same process
+}
+
+impl LockHolder {
+    fn current() -> LockHolder {
+        LockHolder {
+            host: hostname().unwrap_or_else(|| "(unknown host)".to_owned()),
+            user: std::env::var("USERNAME").or_else(|_| std::env::var("USER")).unwrap_or_else(|_| "(unknown user)".to_owned()),
+            pid: std::process::id(),
+            time: SystemTime::now(),
+            token: Uuid::new_v4(),
+        }
+    }
+
+    /// A human-readable description of the holder, e.g. "alice on DESKTOP-1 (process 1234) since 2019-10-18 15:30:00".
+    pub fn describe(&self) -> String {
+        let time = DateTime::<Local>::from(self.time).format("%Y-%m-%d %H:%M:%S");
+        format!("{} on {} (process {}) since {}", self.user, self.host, self.pid, time)
+    }
+
+    /// Whether the holder's process is still running. Processes on other machines can't be checked, so they're
+    /// assumed to be.
+    pub fn is_running(&self) -> bool {
+        self.host != LockHolder::current().host || process_running(self.pid)
+    }
+}
+
+#[cfg(windows)]
+fn process_running(pid: u32) -> bool {
+    use winapi::um::{processthreadsapi, handleapi, minwinbase::STILL_ACTIVE, winnt::PROCESS_QUERY_LIMITED_INFORMATION};
+    unsafe {
+        let process = processthreadsapi::OpenProcess(PROCESS_QUERY_LIMITED_INFORMATION, 0, pid);
+        if process.is_null() { return false }
+        let mut code = 0;
+        let running = processthreadsapi::GetExitCodeProcess(process, &mut code) == 0 || code == STILL_ACTIVE;
+        handleapi::CloseHandle(process);
+        running
+    }
+}
+
+#[cfg(not(windows))]
+fn process_running(pid: u32) -> bool {
+    // Signal 0 only checks whether the process exists. If that can't be found out, it's safer to assume it does.
+    let status = std::process::Command::new("kill").arg("-0").arg(pid.to_string())
+        .stdout(std::process::Stdio::null()).stderr(std::process::Stdio::null()).status();
+    status.map_or(true, |s| s.success())
+}
+
+fn hostname() -> Option<String> {
+    std::env::var("COMPUTERNAME").or_else(|_| std::env::var("HOSTNAME")).ok()
+        .or_else(|| fs::read_to_string("/etc/hostname").ok().map(|h| h.trim().to_owned()))
+        .filter(|h| !h.is_empty())
+}
+
+/// An advisory lock on a set file, which tells anyone else opening it that it's being edited. The lock is a file next
+/// to the set, e.g. `walls.bgs.lock`, and is deleted when this is dropped.
+#[derive(Debug)]
+pub struct SetLock {
+    set_path: PathBuf,
+    holder: LockHolder,
+}
+
+impl SetLock {
+    /// Lock the set file at `set_path`. If `force` is false and anyone holds the lock, even another set in this
+    /// process, fail with `Error::Locked`.
+    pub fn acquire(set_path: &Path, force: bool) -> Result<SetLock, Error> {
+        let path = lock_path(set_path)?;
+        let holder = LockHolder::current();
+        match OpenOptions::new().write(true).create_new(true).open(&path) {
+            Ok(file) => write_holder(file, &holder)?,
+            Err(e) if e.kind() == ErrorKind::AlreadyExists => match SetLock::holder(set_path) {
+                Some(existing) if !force => return Err(Error::Locked(Some(existing))),
+                None if !force => return Err(Error::Locked(None)),
+                _ => write_holder(File::create(&path)?, &holder)?,
+            },
+            Err(e) => return Err(e.into()),
+        }
+        Ok(SetLock { set_path: set_path.to_owned(), holder })
+    }
+
+    /// Who holds the lock on the set file at `set_path`, if anyone. This is `None` if the lock file can't be read.
+    pub fn holder(set_path: &Path) -> Option<LockHolder> {
+        let file = File::open(lock_path(set_path).ok()?).ok()?;
+        serde_json::from_reader(io::BufReader::new(file)).ok()
+    }
+
+    /// Whether the lock file still names this lock's holder. Someone else may have broken the lock since it was taken.
+    pub fn is_held(&self) -> bool {
+        SetLock::holder(&self.set_path).as_ref() == Some(&self.holder)
+    }
+
+    /// The set file this lock is for.
+    pub fn set_path(&self) -> &Path {
+        &self.set_path
+    }
+}
+
+impl Drop for SetLock {
+    fn drop(&mut self) {
+        // If the lock was broken by someone else, it's theirs now.
+        if self.is_held() {
+            if let Ok(path) = lock_path(&self.set_path) { let _ = fs::remove_file(path); }
+        }
+    }
+}
+
+fn lock_path(set_path: &Path) -> Result<PathBuf, Error> {
+    let mut name = match set_path.file_name() {
+        Some(name) => name.to_owned(),
+        None => return Err(io::Error::new(ErrorKind::InvalidInput, "Set files must have names!").into()),
+    };
+    name.push(".lock");
+    Ok(set_path.with_file_name(name))
+}
+
+fn write_holder(file: File, holder: &LockHolder) -> io::Result<()> {
+    serde_json::to_writer(&file, holder)?;
+    file.sync_all()
+}
@@ -0,0 +1,97 @@
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+use std::fs::File;
+use std::io::BufReader;
+
+use blake2::{Blake2b, digest::Digest};
+use serde::{Serialize, Deserialize};
+use uuid::Uuid;
+
+use crate::background::{DesktopBackground, CropRegion, ResizeFilter};
+
+/// The name of the manifest file in the image folder.
+pub(super) const MANIFEST_NAME: &str = ".dbgm-manifest.json";
+
+/// A record of what was written to an image folder, so that a rebuild only has to write the outputs which changed.
+#[derive(Default, Serialize, Deserialize)]
+pub(super) struct Manifest {
+    /// Keyed by file name within the image folder.
+    pub outputs: BTreeMap<String, ManifestEntry>,
+}
+
+#[derive(Clone, Serialize, Deserialize)]
+pub(super) struct ManifestEntry {
+    pub background: Uuid,
+    pub fingerprint: String,
+}
+
+impl Manifest {
+    pub fn path(image_folder: &Path) -> PathBuf {
+        image_folder.join(MANIFEST_NAME)
+    }
+
+    /// Read the manifest in `image_folder`, if there is one. Its presence means dbgm owns the folder. A corrupt
+    /// manifest is treated as empty, so that everything is rebuilt but no old outputs are removed.
+    pub fn load(image_folder: &Path) -> Option<Manifest> {
+        let file = File::open(Manifest::path(image_folder)).ok()?;
+        Some(serde_json::from_reader(BufReader::new(file)).unwrap_or_default())
+    }
+
+    /// For a folder without a manifest, find the outputs written by versions of dbgm from before manifests existed, and
+    /// whether there's anything else in it. Those outputs are PNGs named after a base64 encoded Blake2b hash of their
+    /// pixels; they're returned as a manifest of stale outputs so that the next rebuild replaces them.
+    pub fn find_legacy(image_folder: &Path) -> std::io::Result<(Manifest, bool)> {
+        let mut manifest = Manifest::default();
+        let mut foreign_files = false;
+        for entry in image_folder.read_dir()? {
+            let entry = entry?;
+            match entry.file_name().into_string() {
+                Ok(name) if is_legacy_output(&name) && entry.file_type()?.is_file() => {
+                    manifest.outputs.insert(name, ManifestEntry { background: Uuid::nil(), fingerprint: String::new() });
+                },
+                _ => foreign_files = true,
+            }
+        }
+        Ok((manifest, foreign_files))
+    }
+
+    pub fn save(&self, image_folder: &Path) -> std::io::Result<()> {
+        crate::utils::write_atomically(&Manifest::path(image_folder), |writer| {
+            serde_json::to_writer(writer, self).map_err(std::io::Error::from)
+        })
+    }
+}
+
+/// Whether `name` looks like an output from before manifests existed: 64 bytes of hash, base64 encoded, then ".png".
+fn is_legacy_output(name: &str) -> bool {
+    const ENCODED_HASH_LENGTH: usize = 88;
+    if name.len() != ENCODED_HASH_LENGTH + 4 || !name.ends_with(".png") { return false }
+    let (hash, padding) = name[..ENCODED_HASH_LENGTH].split_at(ENCODED_HASH_LENGTH - 2);
+    padding == "==" && hash.bytes().all(|b| b.is_ascii_alphanumeric() || b == b'-' || b == b'_')
+}
+
+#[derive(Serialize)]
+struct FingerprintData<'a> {
+    key: serde_json::Value, // Includes a hash of the original's content
+    center: (f32, f32),
+    scale: f32,
+    resolution: (usize, usize),
+    filter: ResizeFilter,
+    encoder: &'a str,
+}
+
+/// Everything which affects a background's output, hashed. If it matches the fingerprint of an existing output, that
+/// output doesn't need to be rebuilt.
+pub(super) fn fingerprint(background: &DesktopBackground, crop_region: &CropRegion, resolution: (usize, usize), filter: ResizeFilter, encoder: &str) -> String {
+    let data = FingerprintData {
+        key: (&background.original).into(),
+        center: (crop_region.center.x, crop_region.center.y),
+        scale: crop_region.scale,
+        resolution,
+        filter,
+        encoder,
+    };
+    let mut hasher = Blake2b::new();
+    hasher.input(&serde_json::to_vec(&data).expect("Serializing a fingerprint should never fail!"));
+    base64::encode_config(&hasher.result(), base64::URL_SAFE)
+}
@@ -0,0 +1,582 @@
+use std::collections::HashMap;
+
+use serde_json::Value;
+use uuid::Uuid;
+
+use crate::sources::{self, OriginalKey, CompareKey, KeyRelation};
+use crate::background::EditInfo;
+use crate::background::persist::{SavedBackgroundSet, SavedBackgroundSource, SavedDesktopBackground};
+
+/// One of the two edited copies of a set being merged.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Side { Ours, Theirs }
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum SetField { Name, ImageFolder, Resolution, BackupCount, RelativePaths, ResizeFilter, OutputSettings }
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum BackgroundField { Name, Comments, EditInfo }
+
+/// A change made differently by both sides, or a removal by one side of something the other changed. Sources are
+/// identified by their UUIDs, which are the same in every copy of a set.
+#[derive(Clone, Debug)]
+pub enum Conflict {
+    /// Both sides changed a set-wide setting.
+    Setting(SetField),
+    /// Both sides changed the same field of a source's data, e.g. its name.
+    SourceData { source: Uuid, field: String },
+    /// One side removed a source which the other changed.
+    SourceRemoved { source: Uuid, removed_by: Side },
+    /// Both sides changed the same field of a background. If both sides added it, its UUIDs will differ.
+    Background { source: Uuid, ours: Uuid, theirs: Uuid, field: BackgroundField },
+    /// One side removed a background which the other changed. `background` is its UUID on the side which kept it.
+    BackgroundRemoved { source: Uuid, background: Uuid, removed_by: Side },
+}
+
+/// The result of a three-way merge. Until `resolve` is called, every conflict is settled in favour of our side.
+pub struct Merge {
+    merged: SavedBackgroundSet,
+    theirs: SavedBackgroundSet,
+    pub conflicts: Vec<Conflict>,
+}
+
+impl Merge {
+    /// Merge two copies of a set which were edited separately after being copied from `base`. Sources are matched
+    /// by UUID, and backgrounds by their source and key.
+    pub fn new(base: &SavedBackgroundSet, ours: &SavedBackgroundSet, theirs: SavedBackgroundSet) -> Merge {
+        let mut conflicts = Vec::new();
+        let mut setting = |field, conflicted| if conflicted { conflicts.push(Conflict::Setting(field)) };
+        let (image_folder, conflicted) = merge_value(Some(&base.image_folder), &ours.image_folder, &theirs.image_folder);
+        setting(SetField::ImageFolder, conflicted);
+        let (name, conflicted) = merge_value(Some(&base.name), &ours.name, &theirs.name);
+        setting(SetField::Name, conflicted);
+        let (resolution, conflicted) = merge_value(Some(&base.resolution), &ours.resolution, &theirs.resolution);
+        setting(SetField::Resolution, conflicted);
+        let (backup_count, conflicted) = merge_value(Some(&base.backup_count), &ours.backup_count, &theirs.backup_count);
+        setting(SetField::BackupCount, conflicted);
+        let (relative_paths, conflicted) = merge_value(Some(&base.relative_paths), &ours.relative_paths, &theirs.relative_paths);
+        setting(SetField::RelativePaths, conflicted);
+        let (resize_filter, conflicted) = merge_value(Some(&base.resize_filter), &ours.resize_filter, &theirs.resize_filter);
+        setting(SetField::ResizeFilter, conflicted);
+        let (output_settings, conflicted) = merge_value(Some(&base.output_settings), &ours.output_settings, &theirs.output_settings);
+        setting(SetField::OutputSettings, conflicted);
+
+        let mut merged_sources = Vec::new();
+        for our_source in &ours.sources {
+            let uuid = our_source.uuid;
+            match (find_source(base, uuid), find_source(&theirs, uuid)) {
+                (base_source, Some(their_source)) => {
+                    merged_sources.push(merge_source(base_source, our_source, their_source, &mut conflicts));
+                }
+                (Some(base_source), None) => if source_changed(base_source, our_source) {
+                    conflicts.push(Conflict::SourceRemoved { source: uuid, removed_by: Side::Theirs });
+                    merged_sources.push(our_source.clone());
+                },
+                (None, None) => merged_sources.push(our_source.clone()),
+            }
+        }
+        for their_source in theirs.sources.iter().filter(|s| find_source(ours, s.uuid).is_none()) {
+            match find_source(base, their_source.uuid) {
+                Some(base_source) => if source_changed(base_source, their_source) {
+                    conflicts.push(Conflict::SourceRemoved { source: their_source.uuid, removed_by: Side::Ours });
+                },
+                None => merged_sources.push(their_source.clone()),
+            }
+        }
+
+        let merged = SavedBackgroundSet {
+            version: ours.version,
+            image_folder,
+            name,
+            resolution,
+            backup_count,
+            relative_paths,
+            resize_filter,
+            output_settings,
+            sources: merged_sources,
+        };
+        Merge { merged, theirs, conflicts }
+    }
+
+    /// A human-readable explanation of a conflict, including what each side changed it to where that's short.
+    pub fn describe(&self, conflict: &Conflict) -> String {
+        let (ours, theirs) = (&self.merged, &self.theirs);
+        match *conflict {
+            Conflict::Setting(field) => {
+                let (name, our_value, their_value) = match field {
+                    SetField::Name => ("name", format!("{:?}", ours.name), format!("{:?}", theirs.name)),
+                    SetField::ImageFolder => ("image folder", format!("{:?}", ours.image_folder), format!("{:?}", theirs.image_folder)),
+                    SetField::Resolution => ("resolution", format!("{:?}", ours.resolution), format!("{:?}", theirs.resolution)),
+                    SetField::BackupCount => ("number of backups", ours.backup_count.to_string(), theirs.backup_count.to_string()),
+                    SetField::RelativePaths => ("relative paths setting", ours.relative_paths.to_string(), theirs.relative_paths.to_string()),
+                    SetField::ResizeFilter => ("resize filter", ours.resize_filter.name().to_owned(), theirs.resize_filter.name().to_owned()),
+                    SetField::OutputSettings => ("output settings", ours.output_settings.describe(), theirs.output_settings.describe()),
+                };
+                format!("Both copies changed the set's {}. Ours: {}. Theirs: {}.", name, our_value, their_value)
+            },
+            Conflict::SourceData { source, ref field } => format!("Both copies changed the {} of source {}.", field, self.source_name(source)),
+            Conflict::SourceRemoved { source, removed_by } => format!("Source {} was removed in {}, but changed in {}.",
+                self.source_name(source), removed_by.describe(), removed_by.other().describe()),
+            Conflict::Background { source, ours: our_uuid, theirs: their_uuid, field } => {
+                let our_background = find_source(ours, source).and_then(|s| find_background(s, our_uuid));
+                let their_background = find_source(theirs, source).and_then(|s| find_background(s, their_uuid));
+                let (our_background, their_background) = match (our_background, their_background) {
+                    (Some(o), Some(t)) => (o, t),
+                    _ => return "A background has changed since the merge.".to_owned(),
+                };
+                let (name, our_value, their_value) = match field {
+                    BackgroundField::Name => ("name", format!("{:?}", our_background.name), format!("{:?}", their_background.name)),
+                    BackgroundField::Comments => ("comments", format!("{:?}", our_background.comments), format!("{:?}", their_background.comments)),
+                    BackgroundField::EditInfo => ("crop region", describe_edit_info(&our_background.edit_info), describe_edit_info(&their_background.edit_info)),
+                };
+                format!("Both copies changed the {} of \"{}\". Ours: {}. Theirs: {}.", name, our_background.name, our_value, their_value)
+            },
+            Conflict::BackgroundRemoved { source, background, removed_by } => {
+                let kept = match removed_by { Side::Ours => theirs, Side::Theirs => ours };
+                let name = find_source(kept, source).and_then(|s| find_background(s, background)).map_or("(unknown)", |b| b.name.as_str());
+                format!("\"{}\" in source {} was removed in {}, but changed in {}.",
+                    name, self.source_name(source), removed_by.describe(), removed_by.other().describe())
+            },
+        }
+    }
+
+    /// Settle each conflict in favour of the corresponding side in `sides`, and return the merged set.
+    pub fn resolve(mut self, sides: &[Side]) -> SavedBackgroundSet {
+        let (merged, theirs) = (&mut self.merged, &self.theirs);
+        for (conflict, _) in self.conflicts.iter().zip(sides).filter(|(_, &side)| side == Side::Theirs) {
+            match *conflict {
+                Conflict::Setting(field) => match field {
+                    SetField::Name => merged.name = theirs.name.clone(),
+                    SetField::ImageFolder => merged.image_folder = theirs.image_folder.clone(),
+                    SetField::Resolution => merged.resolution = theirs.resolution,
+                    SetField::BackupCount => merged.backup_count = theirs.backup_count,
+                    SetField::RelativePaths => merged.relative_paths = theirs.relative_paths,
+                    SetField::ResizeFilter => merged.resize_filter = theirs.resize_filter,
+                    SetField::OutputSettings => merged.output_settings = theirs.output_settings.clone(),
+                },
+                Conflict::SourceData { source, ref field } => {
+                    let their_value = find_source(theirs, source).and_then(|s| s.data.get(field)).cloned();
+                    let data = find_source_mut(merged, source).and_then(|s| s.data.as_object_mut());
+                    match (data, their_value) {
+                        (Some(data), Some(value)) => { data.insert(field.clone(), value); },
+                        (Some(data), None) => { data.remove(field); },
+                        _ => {},
+                    }
+                },
+                Conflict::SourceRemoved { source, removed_by: Side::Theirs } => merged.sources.retain(|s| s.uuid != source),
+                Conflict::SourceRemoved { source, removed_by: Side::Ours } => {
+                    merged.sources.extend(find_source(theirs, source).cloned());
+                },
+                Conflict::Background { source, ours, theirs: their_uuid, field } => {
+                    let their_background = match find_source(theirs, source).and_then(|s| find_background(s, their_uuid)) {
+                        Some(background) => background,
+                        None => continue,
+                    };
+                    let background = match find_source_mut(merged, source).and_then(|s| s.backgrounds.iter_mut().find(|b| b.uuid == ours)) {
+                        Some(background) => background,
+                        None => continue,
+                    };
+                    match field {
+                        BackgroundField::Name => background.name = their_background.name.clone(),
+                        BackgroundField::Comments => background.comments = their_background.comments.clone(),
+                        BackgroundField::EditInfo => background.edit_info = their_background.edit_info.clone(),
+                    }
+                },
+                Conflict::BackgroundRemoved { source, background, removed_by: Side::Theirs } => {
+                    if let Some(merged_source) = find_source_mut(merged, source) {
+                        merged_source.backgrounds.retain(|b| b.uuid != background);
+                    }
+                },
+                Conflict::BackgroundRemoved { source, background, removed_by: Side::Ours } => {
+                    let their_background = find_source(theirs, source).and_then(|s| find_background(s, background)).cloned();
+                    if let (Some(merged_source), Some(their_background)) = (find_source_mut(merged, source), their_background) {
+                        merged_source.backgrounds.push(their_background);
+                    }
+                },
+            }
+        }
+        self.merged
+    }
+
+    fn source_name(&self, uuid: Uuid) -> String {
+        find_source(&self.merged, uuid).or_else(|| find_source(&self.theirs, uuid))
+            .and_then(|s| s.data.get("name")).and_then(Value::as_str)
+            .map_or_else(|| format!("{{{}}}", uuid), |name| format!("\"{}\"", name))
+    }
+}
+
+impl Side {
+    fn other(self) -> Side {
+        match self {
+            Side::Ours => Side::Theirs,
+            Side::Theirs => Side::Ours,
+        }
+    }
+
+    fn describe(self) -> &'static str {
+        match self {
+            Side::Ours => "our copy",
+            Side::Theirs => "their copy",
+        }
+    }
+}
+
+/// Merge a value which may have been changed on either side. Returns the merged value, and whether both sides changed
+/// it differently, in which case ours is used. With no base, the value was added by both sides.
+fn merge_value<T: PartialEq + Clone>(base: Option<&T>, ours: &T, theirs: &T) -> (T, bool) {
+    if ours == theirs || Some(theirs) == base { (ours.clone(), false) }
+    else if Some(ours) == base { (theirs.clone(), false) }
+    else { (ours.clone(), true) }
+}
+
+/// The field of a source's data where it keeps what it last found of its originals, e.g. their paths and timestamps.
+/// This is the source's own record rather than anything the user chose, and it differs between machines even when
+/// nothing was changed, so it never conflicts. The next reload corrects anything the merge gets out of date.
+const ORIGINALS: &str = "originals";
+
+/// Merge the `ORIGINALS` of a source entry by entry, or return `None` if they aren't a map. Sources store them as
+/// either an object, or a list of key and value pairs when the keys are paths. Entries are kept unless both sides
+/// have removed them, so no background loses its original, and where both sides changed an entry ours is used.
+fn merge_originals(base: Option<&Value>, ours: &Value, theirs: &Value) -> Option<Value> {
+    // Both forms are handled as (key, entry) pairs, and keys are compared by their JSON text.
+    let entries = |originals: &Value| -> Option<Vec<(Value, Value)>> {
+        match originals {
+            Value::Object(entries) => Some(entries.iter().map(|(k, entry)| (Value::String(k.clone()), entry.clone())).collect()),
+            Value::Array(pairs) => pairs.iter().map(|pair| match pair.as_array().map(Vec::as_slice) {
+                Some([k, entry]) => Some((k.clone(), entry.clone())),
+                _ => None,
+            }).collect(),
+            _ => None,
+        }
+    };
+    let base_entries: HashMap<String, Value> = base.and_then(entries).unwrap_or_default()
+        .into_iter().map(|(k, entry)| (k.to_string(), entry)).collect();
+
+    let mut merged = entries(ours)?;
+    let index: HashMap<String, usize> = merged.iter().enumerate().map(|(i, (k, _))| (k.to_string(), i)).collect();
+    for (k, their_entry) in entries(theirs)? {
+        match index.get(&k.to_string()) {
+            Some(&i) => if base_entries.get(&k.to_string()) == Some(&merged[i].1) { merged[i].1 = their_entry },
+            None => merged.push((k, their_entry)),
+        }
+    }
+
+    Some(match ours {
+        Value::Object(_) => Value::Object(merged.into_iter().filter_map(|(k, entry)| Some((k.as_str()?.to_owned(), entry))).collect()),
+        _ => Value::Array(merged.into_iter().map(|(k, entry)| Value::Array(vec![k, entry])).collect()),
+    })
+}
+
+fn merge_source(
+    base: Option<&SavedBackgroundSource>,
+    ours: &SavedBackgroundSource,
+    theirs: &SavedBackgroundSource,
+    conflicts: &mut Vec<Conflict>
+) -> SavedBackgroundSource {
+    let uuid = ours.uuid;
+    // Sources are structs, so their data can be merged field by field.
+    let data = match (ours.data.as_object(), theirs.data.as_object()) {
+        (Some(our_data), Some(their_data)) => {
+            let base_data = base.and_then(|b| b.data.as_object());
+            let mut data = our_data.clone();
+            for field in our_data.keys().chain(their_data.keys().filter(|k| !our_data.contains_key(*k))) {
+                if field == ORIGINALS {
+                    let base_originals = base_data.and_then(|b| b.get(field));
+                    let originals = match (our_data.get(field), their_data.get(field)) {
+                        (Some(ours), Some(theirs)) => merge_originals(base_originals, ours, theirs),
+                        (ours, theirs) => ours.or(theirs).cloned(),
+                    };
+                    if let Some(originals) = originals { data.insert(field.clone(), originals); }
+                    continue
+                }
+                let base_value = base_data.map(|b| b.get(field));
+                let (value, conflicted) = merge_value(base_value.as_ref(), &our_data.get(field), &their_data.get(field));
+                if conflicted { conflicts.push(Conflict::SourceData { source: uuid, field: field.clone() }); }
+                match value {
+                    Some(value) => { data.insert(field.clone(), value.clone()); },
+                    None => { data.remove(field); },
+                }
+            }
+            Value::Object(data)
+        },
+        _ => {
+            let (data, conflicted) = merge_value(base.map(|b| &b.data), &ours.data, &theirs.data);
+            if conflicted { conflicts.push(Conflict::SourceData { source: uuid, field: "data".to_owned() }); }
+            data
+        }
+    };
+
+    let source = sources::load_source_by_id(&ours.ty, data.clone()).ok();
+    let key = |background: &SavedDesktopBackground| match &source {
+        Some(source) if source.check_key(&background.key_data).is_ok() => MatchKey::Valid(source.assemble_key(background.key_data.clone())),
+        _ => MatchKey::Raw(background.key_data.clone()),
+    };
+    let empty = Vec::new();
+    let base_backgrounds = base.map_or(&empty, |b| &b.backgrounds);
+
+    // Group the backgrounds which refer to the same original, as (base, ours, theirs).
+    let mut groups: Vec<(Option<&SavedDesktopBackground>, Option<&SavedDesktopBackground>, Option<&SavedDesktopBackground>)> =
+        ours.backgrounds.iter().map(|b| (None, Some(b), None)).collect();
+    let mut group_keys: Vec<MatchKey> = ours.backgrounds.iter().map(&key).collect();
+    for (other, is_base) in vec![(base_backgrounds, true), (&theirs.backgrounds, false)] {
+        let keys: Vec<MatchKey> = other.iter().map(&key).collect();
+        let matches = match_keys(&group_keys, &keys);
+        for ((background, matched), key) in other.iter().zip(matches).zip(keys) {
+            let group = match matched {
+                Some(group) => group,
+                None => {
+                    groups.push((None, None, None));
+                    group_keys.push(key);
+                    groups.len() - 1
+                }
+            };
+            match is_base {
+                true => groups[group].0 = Some(background),
+                false => groups[group].2 = Some(background),
+            }
+        }
+    }
+
+    let mut backgrounds = Vec::new();
+    for group in groups {
+        match group {
+            (base, Some(ours), Some(theirs)) => backgrounds.push(merge_background(uuid, base, ours, theirs, conflicts)),
+            (Some(base), Some(ours), None) => if background_changed(base, ours) {
+                conflicts.push(Conflict::BackgroundRemoved { source: uuid, background: ours.uuid, removed_by: Side::Theirs });
+                backgrounds.push(ours.clone());
+            },
+            (Some(base), None, Some(theirs)) => if background_changed(base, theirs) {
+                conflicts.push(Conflict::BackgroundRemoved { source: uuid, background: theirs.uuid, removed_by: Side::Ours });
+            },
+            (None, Some(added), None) | (None, None, Some(added)) => backgrounds.push(added.clone()),
+            (_, None, None) => {},
+        }
+    }
+
+    SavedBackgroundSource { ty: ours.ty.clone(), uuid, version: ours.version, data, backgrounds }
+}
+
+fn merge_background(
+    source: Uuid,
+    base: Option<&SavedDesktopBackground>,
+    ours: &SavedDesktopBackground,
+    theirs: &SavedDesktopBackground,
+    conflicts: &mut Vec<Conflict>
+) -> SavedDesktopBackground {
+    let mut conflict = |field, conflicted| if conflicted {
+        conflicts.push(Conflict::Background { source, ours: ours.uuid, theirs: theirs.uuid, field });
+    };
+    let (name, conflicted) = merge_value(base.map(|b| &b.name), &ours.name, &theirs.name);
+    conflict(BackgroundField::Name, conflicted);
+    let (comments, conflicted) = merge_value(base.map(|b| &b.comments), &ours.comments, &theirs.comments);
+    conflict(BackgroundField::Comments, conflicted);
+    let (edit_info, conflicted) = merge_value(base.map(|b| &b.edit_info), &ours.edit_info, &theirs.edit_info);
+    conflict(BackgroundField::EditInfo, conflicted);
+
+    // Each flag is a yes or no, so changes to them can always be merged: a flag changed by either side is changed.
+    // Without a base, both sides added the background, so a flag either of them set is set.
+    let flags = match base {
+        Some(base) => base.flags ^ ((ours.flags ^ base.flags) | (theirs.flags ^ base.flags)),
+        None => ours.flags | theirs.flags,
+    };
+
+    // The rest describes the original, so it's taken from whichever side has seen it change, if either.
+    let original_side = match base {
+        Some(base) if ours.key_data == base.key_data => theirs,
+        _ => ours,
+    };
+    SavedDesktopBackground {
+        uuid: ours.uuid,
+        name,
+        location: original_side.location.clone(),
+        comments,
+        key_data: original_side.key_data.clone(),
+        flags,
+        original_meta: original_side.original_meta.clone(),
+        edit_info,
+    }
+}
+
+/// Whether a source has changed in a way that removing it would lose.
+fn source_changed(base: &SavedBackgroundSource, other: &SavedBackgroundSource) -> bool {
+    let settings = |data: &Value| {
+        let mut data = data.clone();
+        if let Some(data) = data.as_object_mut() { data.remove(ORIGINALS); }
+        data
+    };
+    settings(&base.data) != settings(&other.data) || base.backgrounds.len() != other.backgrounds.len()
+        || base.backgrounds.iter().zip(&other.backgrounds).any(|(b, o)| b.key_data != o.key_data || background_changed(b, o))
+}
+
+/// Whether the user has changed a background in a way that removing it would lose.
+fn background_changed(base: &SavedDesktopBackground, other: &SavedDesktopBackground) -> bool {
+    base.name != other.name || base.comments != other.comments || base.edit_info != other.edit_info || base.flags != other.flags
+}
+
+fn describe_edit_info(edit_info: &Option<EditInfo>) -> String {
+    match edit_info {
+        Some(EditInfo { center, scale }) => format!("centered at ({}, {}) with scale {}", center.x, center.y, scale),
+        None => "not cropped".to_owned(),
+    }
+}
+
+enum MatchKey {
+    Valid(OriginalKey),
+    /// A key which its source can't read, or whose source couldn't be loaded. These only match identical keys.
+    Raw(Value),
+}
+
+/// Match each of `keys` to a different one of `targets` which refers to the same original, if there is one. Exact
+/// matches are found first, and then matches with originals which have been altered.
+fn match_keys(targets: &[MatchKey], keys: &[MatchKey]) -> Vec<Option<usize>> {
+    let mut exact: HashMap<&OriginalKey, Vec<usize>> = HashMap::new();
+    for (i, target) in targets.iter().enumerate().rev() {
+        if let MatchKey::Valid(key) = target { exact.entry(key).or_default().push(i); }
+    }
+    let mut taken = vec![false; targets.len()];
+    let mut matches = vec![None; keys.len()];
+    for (key, matched) in keys.iter().zip(&mut matches) {
+        *matched = match key {
+            MatchKey::Valid(key) => exact.get_mut(key).and_then(|candidates| {
+                std::iter::from_fn(|| candidates.pop()).find(|&i| !taken[i])
+            }),
+            MatchKey::Raw(value) => (0..targets.len()).find(|&i| !taken[i] && match &targets[i] {
+                MatchKey::Raw(target) => target == value,
+                _ => false,
+            }),
+        };
+        if let Some(i) = *matched { taken[i] = true; }
+    }
+    for (key, matched) in keys.iter().zip(&mut matches).filter(|(_, m)| m.is_none()) {
+        if let MatchKey::Valid(key) = key {
+            *matched = (0..targets.len()).find(|&i| !taken[i] && match &targets[i] {
+                MatchKey::Valid(target) => target.compare(key) != KeyRelation::Distinct,
+                _ => false,
+            });
+            if let Some(i) = *matched { taken[i] = true; }
+        }
+    }
+    matches
+}
+
+fn find_source(set: &SavedBackgroundSet, uuid: Uuid) -> Option<&SavedBackgroundSource> {
+    set.sources.iter().find(|s| s.uuid == uuid)
+}
+
+fn find_source_mut(set: &mut SavedBackgroundSet, uuid: Uuid) -> Option<&mut SavedBackgroundSource> {
+    set.sources.iter_mut().find(|s| s.uuid == uuid)
+}
+
+fn find_background(source: &SavedBackgroundSource, uuid: Uuid) -> Option<&SavedDesktopBackground> {
+    source.backgrounds.iter().find(|b| b.uuid == uuid)
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+    use super::*;
+    use crate::background::DesktopBackgroundFlags;
+    use crate::background::persist::SavedOriginalMeta;
+
+    /// A background whose source can't be loaded, so it's matched by its exact key.
+    fn background(uuid: u128, key: &str) -> SavedDesktopBackground {
+        SavedDesktopBackground {
+            uuid: Uuid::from_u128(uuid),
+            name: key.to_owned(),
+            location: String::new(),
+            comments: String::new(),
+            key_data: json!(key),
+            flags: DesktopBackgroundFlags::empty(),
+            original_meta: SavedOriginalMeta { last_known_size: None },
+            edit_info: None,
+        }
+    }
+
+    fn set(backgrounds: &[SavedDesktopBackground]) -> SavedBackgroundSet {
+        serde_json::from_value(json!({
+            "version": 3,
+            "image_folder": "/walls/out",
+            "name": "Walls",
+            "resolution": [1920, 1080],
+            "sources": [{
+                "ty": "test",
+                "uuid": Uuid::from_u128(100),
+                "version": 0,
+                "data": { "name": "Walls" },
+                "backgrounds": backgrounds,
+            }],
+        })).unwrap()
+    }
+
+    fn backgrounds(set: &SavedBackgroundSet) -> &[SavedDesktopBackground] {
+        &set.sources[0].backgrounds
+    }
+
+    #[test]
+    fn background_added_on_one_side_is_kept() {
+        let base = set(&[background(1, "a")]);
+        let merge = Merge::new(&base, &set(&[background(1, "a")]), set(&[background(1, "a"), background(2, "b")]));
+        assert!(merge.conflicts.is_empty());
+        let merged = merge.resolve(&[]);
+        let names: Vec<_> = backgrounds(&merged).iter().map(|b| b.name.as_str()).collect();
+        assert_eq!(names, ["a", "b"]);
+    }
+
+    #[test]
+    fn edit_on_one_side_is_taken() {
+        let base = set(&[background(1, "a")]);
+        let mut edited = background(1, "a");
+        edited.comments = "Sunset".to_owned();
+        edited.flags = DesktopBackgroundFlags::EXCLUDED;
+        let merge = Merge::new(&base, &set(&[background(1, "a")]), set(&[edited]));
+        assert!(merge.conflicts.is_empty());
+        let merged = merge.resolve(&[]);
+        assert_eq!(backgrounds(&merged)[0].comments, "Sunset");
+        assert_eq!(backgrounds(&merged)[0].flags, DesktopBackgroundFlags::EXCLUDED);
+    }
+
+    #[test]
+    fn conflicting_edits_can_be_resolved_either_way() {
+        let base = set(&[background(1, "a")]);
+        let (mut ours, mut theirs) = (background(1, "a"), background(1, "a"));
+        ours.name = "Ours".to_owned();
+        theirs.name = "Theirs".to_owned();
+        let (ours, theirs) = (set(&[ours]), set(&[theirs]));
+
+        let merge = Merge::new(&base, &ours, set(backgrounds(&theirs)));
+        match merge.conflicts.as_slice() {
+            [Conflict::Background { field: BackgroundField::Name, .. }] => {},
+            conflicts => panic!("{:?}", conflicts),
+        }
+        assert_eq!(backgrounds(&merge.resolve(&[Side::Ours]))[0].name, "Ours");
+        let merge = Merge::new(&base, &ours, set(backgrounds(&theirs)));
+        assert_eq!(backgrounds(&merge.resolve(&[Side::Theirs]))[0].name, "Theirs");
+    }
+
+    #[test]
+    fn removal_of_an_edited_background_conflicts() {
+        let base = set(&[background(1, "a")]);
+        let mut edited = background(1, "a");
+        edited.comments = "Keep me".to_owned();
+        let merge = Merge::new(&base, &set(&[]), set(&[edited.clone()]));
+        match merge.conflicts.as_slice() {
+            [Conflict::BackgroundRemoved { removed_by: Side::Ours, .. }] => {},
+            conflicts => panic!("{:?}", conflicts),
+        }
+        assert!(backgrounds(&merge.resolve(&[Side::Ours])).is_empty());
+        let merge = Merge::new(&base, &set(&[]), set(&[edited]));
+        assert_eq!(backgrounds(&merge.resolve(&[Side::Theirs]))[0].comments, "Keep me");
+    }
+
+    #[test]
+    fn flags_of_a_background_added_by_both_sides_are_combined() {
+        let (mut ours, mut theirs) = (background(1, "a"), background(2, "a"));
+        ours.flags = DesktopBackgroundFlags::EXCLUDED;
+        theirs.flags = DesktopBackgroundFlags::UNEDITED;
+        let merge = Merge::new(&set(&[]), &set(&[ours]), set(&[theirs]));
+        assert!(merge.conflicts.is_empty());
+        let merged = merge.resolve(&[]);
+        assert_eq!(backgrounds(&merged).len(), 1);
+        assert_eq!(backgrounds(&merged)[0].flags, DesktopBackgroundFlags::EXCLUDED | DesktopBackgroundFlags::UNEDITED);
+    }
+}
@@ -0,0 +1,92 @@
+use serde_json::Value;
+use uuid::Uuid;
+
+use crate::sources;
+use crate::background::SetLoadWarning;
+use crate::background::persist::SavedBackgroundSource;
+
+/// The version of the set file format written by this version of the program.
+pub const SET_FORMAT_VERSION: u32 = 3;
+
+/// The migration at each index upgrades a set document from that version to the next one.
+const SET_MIGRATIONS: [fn(&mut Value); SET_FORMAT_VERSION as usize] = [
+    add_source_versions,
+    add_uuids,
+    compact_encodings,
+];
+
+/// Upgrade a set document written by an older version, one version at a time, and then upgrade each of
+/// its sources. Documents written by a newer version are left alone, with a warning.
+pub(super) fn upgrade(document: &mut Value) -> Vec<SetLoadWarning> {
+    let mut warnings = Vec::new();
+    let mut version = document.get("version").and_then(Value::as_u64).unwrap_or(0) as u32;
+    if version > SET_FORMAT_VERSION {
+        warnings.push(SetLoadWarning::NewerVersion { version });
+        return warnings
+    }
+    while version < SET_FORMAT_VERSION {
+        SET_MIGRATIONS[version as usize](document);
+        version += 1;
+    }
+    set_version(document, version);
+
+    let saved_sources = document.get_mut("sources").and_then(Value::as_array_mut);
+    for saved in saved_sources.into_iter().flatten() {
+        let ty = saved.get("ty").and_then(Value::as_str).unwrap_or_default().to_owned();
+        let version = saved.get("version").and_then(Value::as_u64).unwrap_or(0) as u32;
+        // Sources from a newer version are left for `SavedBackgroundSet::load` to keep unloaded.
+        if version < sources::source_version(&ty) {
+            let version = sources::migrate_source(&ty, version, saved);
+            set_version(saved, version);
+        }
+    }
+    warnings
+}
+
+/// Upgrade the sources of a set which is otherwise current, when they were written by an older version.
+pub(super) fn upgrade_sources(saved_sources: &mut [SavedBackgroundSource]) -> Result<(), serde_json::Error> {
+    for saved in saved_sources.iter_mut().filter(|s| s.version < sources::source_version(&s.ty)) {
+        let mut document = serde_json::to_value(&*saved)?;
+        let version = sources::migrate_source(&saved.ty, saved.version, &mut document);
+        set_version(&mut document, version);
+        *saved = serde_json::from_value(document)?;
+    }
+    Ok(())
+}
+
+/// Sets written before the format was versioned didn't record versions for their sources either.
+fn add_source_versions(document: &mut Value) {
+    let saved_sources = document.get_mut("sources").and_then(Value::as_array_mut);
+    for saved in saved_sources.into_iter().flatten() {
+        set_version(saved, 0);
+    }
+}
+
+/// Version 2 gave every source and background a persistent identifier.
+fn add_uuids(document: &mut Value) {
+    let saved_sources = document.get_mut("sources").and_then(Value::as_array_mut);
+    for saved in saved_sources.into_iter().flatten() {
+        let backgrounds = saved.get_mut("backgrounds").and_then(Value::as_array_mut);
+        for background in backgrounds.into_iter().flatten() {
+            add_uuid(background);
+        }
+        add_uuid(saved);
+    }
+}
+
+/// Version 3 saved hashes as base64 strings, and UUIDs in CBOR sets as bytes. Older versions can't read those, but
+/// older documents can still be read as they are.
+fn compact_encodings(_document: &mut Value) {}
+
+fn add_uuid(value: &mut Value) {
+    if let Some(object) = value.as_object_mut() {
+        object.entry("uuid").or_insert_with(|| Uuid::new_v4().to_string().into());
+    }
+}
+
+fn set_version(value: &mut Value, version: u32) {
+    // Anything that isn't an object will fail to deserialize anyway, so it's fine to skip it here.
+    if let Some(object) = value.as_object_mut() {
+        object.insert("version".to_owned(), version.into());
+    }
+}
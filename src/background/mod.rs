@@ -1,22 +1,45 @@
 use bitflags::bitflags;
 use image::{self, ImageResult, DynamicImage, GenericImageView};
 use serde::{Serialize, Deserialize};
+use uuid::Uuid;
 
 use crate::math::Vec2;
 use crate::sources::{OriginalKey, CompareKey, KeyRelation};
 
 mod set;
 mod persist;
-pub use set::{BackgroundSet, SkipReason};
+mod migrate;
+mod backup;
+mod recovery;
+mod watch;
+mod check;
+mod lock;
+mod merge;
+mod manifest;
+mod rebuild;
+mod resample;
+mod encode;
+pub use set::BackgroundSet;
+pub use persist::{Error, SavedBackgroundSet, SetFormat, UnloadedSource, SetLoadWarning};
+pub use migrate::SET_FORMAT_VERSION;
+pub use backup::{Backup, list_backups};
+pub use recovery::{Recovery, RecoveryFile};
+pub use watch::SourceWatcher;
+pub use check::{Problem, Repair};
+pub use lock::{OpenMode, LockHolder, SetLock};
+pub use merge::{Merge, Conflict, Side, SetField, BackgroundField};
+pub use rebuild::{Rebuild, SkipReason, RebuildError};
+pub use resample::ResizeFilter;
+pub use encode::{OutputSettings, OutputFormat, PngCompression};
 
-#[derive(Clone, Debug, Serialize, Deserialize)]
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
 struct EditInfo { pub center: Vec2, pub scale: f32 }
 
 impl EditInfo {
     fn default_sized(crop_size: impl Into<Vec2>, tex_size: impl Into<Vec2>) -> EditInfo {
         let (crop_size, tex_size) = (crop_size.into(), tex_size.into());
         EditInfo {
-            center: tex_size / 2.0 + [0.5, 0.5], 
+            center: tex_size / 2.0,
             scale: f32::min(tex_size.x / crop_size.x, tex_size.y / crop_size.y),
         }
     }
@@ -60,6 +83,7 @@ bitflags! {
 }
 
 pub struct DesktopBackground {
+    uuid: Uuid, // Identifies the background persistently, unlike its ID in the set
     pub name: String,
     pub location: String,
     pub comments: String,
@@ -74,6 +98,7 @@ impl DesktopBackground {
     /// Create a new DesktopBackground from an Original.
     pub fn from_original(source: usize, key: OriginalKey, original: &dyn Original) -> DesktopBackground {
         DesktopBackground {
+            uuid: Uuid::new_v4(),
             name: original.name(),
             location: original.location(), // TODO: Figure out how this should work
             comments: String::new(),
@@ -85,6 +110,10 @@ impl DesktopBackground {
         }
     }
 
+    pub fn uuid(&self) -> Uuid {
+        self.uuid
+    }
+
     /// Update this background when changes have been made to its original. 
     pub fn update_from(&mut self, key: OriginalKey, original: &dyn Original) {
         assert!(key.compare(&self.original) != KeyRelation::Distinct);
@@ -99,6 +128,13 @@ impl DesktopBackground {
         }
     }
 
+    /// Point this background at an original which has moved to a new key, keeping all of its edits.
+    pub fn repoint(&mut self, key: OriginalKey, original: &dyn Original) {
+        self.location = original.location();
+        self.original = key;
+        self.original_meta = OriginalMeta::load(original, Some(&self.original_meta));
+    }
+
     /// Returns true if the original image file for this background cannot be accessed.
     pub fn is_unavailable(&self) -> bool {
         match self.original_meta {
@@ -178,10 +214,10 @@ impl CropRegion {
         self.center + (self.scale * self.crop_size / 2.0)
     }
 
-    pub fn crop<'i, I: image::GenericImageView>(&self, image: &'i mut I) -> image::SubImage<&'i mut I> {
-        let (top_left, bottom_right) = (self.top_left().floor(), self.bottom_right().ceil());
-        let size = bottom_right - top_left;
-        image::imageops::crop(image, top_left.x as u32, top_left.y as u32, size.x as u32, size.y as u32)
+    /// Crop `image` to this region, scaled to exactly `crop_size` with the given filter.
+    pub fn crop(&self, image: &DynamicImage, filter: ResizeFilter) -> image::RgbaImage {
+        let output_size = (self.crop_size.x.round() as u32, self.crop_size.y.round() as u32);
+        resample::resample(image, self.top_left(), self.scale * self.crop_size, output_size, filter)
     }
 }
 
@@ -215,4 +251,6 @@ pub trait Original {
     fn read_image(&self) -> ImageResult<DynamicImage>;
     fn name(&self) -> String;
     fn location(&self) -> String;
+    /// A copy of this original which can be read on another thread.
+    fn boxed_clone(&self) -> Box<dyn Original + Send + Sync>;
 }
\ No newline at end of file
@@ -1,34 +1,102 @@
 use std::path::{Path, PathBuf};
+use std::collections::HashMap;
 use std::fs::File;
-use std::io::{BufReader, BufWriter};
+use std::io::{Read, Write};
 
 use serde::*;
 use stable_vec::StableVec;
+use uuid::Uuid;
 
 use crate::sources::{self, OriginalResult};
 use crate::background::*;
+use crate::background::migrate::{self, SET_FORMAT_VERSION};
+use crate::background::backup;
+use crate::utils::portable_path;
 
 impl BackgroundSet {
-    pub fn load(path: impl AsRef<Path>) -> Result<(BackgroundSet, Vec<SetLoadWarning>), Error> {
-        let file = File::open(path)?;
-        let reader = BufReader::new(file);
-        let saved_data: SavedBackgroundSet = serde_json::from_reader(reader)?;
-        Ok(saved_data.load())
-    }
-
-    pub fn save(&self, path: impl AsRef<Path>) -> Result<(), Error> {
-        let file = File::create(path)?;
-        let writer = BufWriter::new(file);
-        let saved_data = SavedBackgroundSet {
-            // These are expect calls rather than Err returns because there is no reason to gracefully 
-            // handle these errors here when the UI code must check the preconditions itself anyway.
-            image_folder: self.image_folder.clone().expect("Cannot save background set without image folder!"),
-            name: self.name.clone().expect("Cannot save background set without a name!"),
+    /// Load a set from `path`, in whichever format it was saved. Unless `mode` is `OpenMode::ReadOnly`, the file is
+    /// locked until the set is dropped. Sets saved by a newer version are never locked, since they can't be saved
+    /// back to the same file.
+    pub fn load(path: impl AsRef<Path>, mode: OpenMode) -> Result<(BackgroundSet, Vec<SetLoadWarning>), Error> {
+        let path = path.as_ref();
+        let lock = match mode {
+            OpenMode::Edit => Some(SetLock::acquire(path, false)?),
+            OpenMode::BreakLock => Some(SetLock::acquire(path, true)?),
+            OpenMode::ReadOnly => None,
+        };
+        let mut data = Vec::new();
+        File::open(path)?.read_to_end(&mut data)?;
+        // Paths may be stored relative to the set file, whether or not the set still asks for that.
+        let (mut set, warnings) = portable_path::with_base(path.parent(), || SetFormat::detect(&data).read(&data))?;
+        let newer_version = warnings.iter().filter_map(|w| match w {
+            SetLoadWarning::NewerVersion { version } => Some(*version),
+            _ => None,
+        }).next();
+        match newer_version {
+            Some(version) => set.newer_file = Some((path.to_owned(), version)),
+            None => set.lock = lock,
+        }
+        Ok((set, warnings))
+    }
+
+    /// Load a set from a parsed set file, upgrading it first if it was written by an older version.
+    pub(super) fn from_document(mut document: serde_json::Value) -> Result<(BackgroundSet, Vec<SetLoadWarning>), Error> {
+        let mut warnings = migrate::upgrade(&mut document);
+        let saved_data: SavedBackgroundSet = serde_json::from_value(document)?;
+        let (set, load_warnings) = saved_data.load();
+        warnings.extend(load_warnings);
+        Ok((set, warnings))
+    }
+
+    /// Save the set to `path`, in the format its extension asks for. The file is replaced atomically, after copying
+    /// its old contents to a backup. Saving a set under the other extension converts it. The set must hold the lock
+    /// on `path`, or be able to take it, and afterwards holds that lock instead of any other. A lock it already holds
+    /// is checked again first, in case someone else has broken it. If the set was loaded from a file saved by a newer
+    /// version, it's never saved back there, as whatever this version didn't understand of it would be lost.
+    pub fn save(&mut self, path: impl AsRef<Path>) -> Result<(), Error> {
+        // These are expect calls rather than Err returns because there is no reason to gracefully 
+        // handle these errors here when the UI code must check the preconditions itself anyway.
+        self.image_folder.as_ref().expect("Cannot save background set without image folder!");
+        self.name.as_ref().expect("Cannot save background set without a name!");
+
+        let path = path.as_ref();
+        match &self.newer_file {
+            Some((file, version)) if file == path => return Err(Error::NewerVersion(*version)),
+            _ => {},
+        }
+        // If someone broke the lock since it was taken, this fails rather than saving over their changes.
+        let new_lock = match &self.lock {
+            Some(lock) if lock.set_path() == path && lock.is_held() => None,
+            _ => Some(SetLock::acquire(path, false)?),
+        };
+        backup::back_up(path, self.backup_count)?;
+        let base = if self.relative_paths { path.parent() } else { None };
+        portable_path::with_base(base, || {
+            let saved_data = self.to_saved();
+            crate::utils::write_atomically(path, |writer| SetFormat::from_path(path).write(writer, &saved_data))
+        })?;
+        if new_lock.is_some() { self.lock = new_lock; }
+        Ok(())
+    }
+
+    /// The set as it would be saved, e.g. to check it for problems.
+    pub fn to_saved(&self) -> SavedBackgroundSet {
+        SavedBackgroundSet {
+            version: SET_FORMAT_VERSION,
+            image_folder: self.image_folder.clone(),
+            name: self.name.clone(),
             resolution: self.resolution,
+            backup_count: self.backup_count,
+            relative_paths: self.relative_paths,
+            resize_filter: self.resize_filter,
+            output_settings: self.output_settings.clone(),
             sources: self.sources.iter().map(|(id, source)| SavedBackgroundSource {
                 ty: source.source_type_id().to_owned(),
+                uuid: self.source_uuids[&id],
+                version: sources::source_version(source.source_type_id()),
                 data: serde_json::to_value(source.as_serialize()).expect("Serializing a source should never fail!"),
                 backgrounds: self.backgrounds.values().filter(|b| b.source == id).map(|b| SavedDesktopBackground {
+                    uuid: b.uuid,
                     name: b.name.clone(),
                     location: b.location.clone(),
                     comments: b.comments.clone(),
@@ -37,9 +105,87 @@ impl BackgroundSet {
                     original_meta: SavedOriginalMeta { last_known_size: b.original_meta.last_known_size() },
                     edit_info: b.edit_info.clone(),
                 }).collect()
-            }).collect()
+            }).chain(self.unloaded_sources.iter().map(|u| u.saved.clone())).collect()
+        }
+    }
+}
+
+/// The encodings a set file can be saved in.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum SetFormat {
+    /// Readable, but slow to load for large sets. Used for `.bgs` files.
+    Json,
+    /// Much smaller and faster to load. Used for `.bgsb` files.
+    Cbor,
+}
+
+/// The CBOR self-describe tag, which starts every CBOR set file so that it's recognized whatever it's called.
+const CBOR_MAGIC: [u8; 3] = [0xd9, 0xd9, 0xf7];
+
+impl SetFormat {
+    pub const CBOR_EXTENSION: &'static str = "bgsb";
+
+    /// The format a set saved to `path` should use.
+    pub fn from_path(path: &Path) -> SetFormat {
+        match path.extension() {
+            Some(extension) if extension == SetFormat::CBOR_EXTENSION => SetFormat::Cbor,
+            _ => SetFormat::Json,
+        }
+    }
+
+    /// Find the format of a set file from its first few bytes.
+    fn detect(data: &[u8]) -> SetFormat {
+        match data.starts_with(&CBOR_MAGIC) {
+            true => SetFormat::Cbor,
+            false => SetFormat::Json,
+        }
+    }
+
+    /// The format version of a set file in this format, without loading the rest of it.
+    fn version(self, data: &[u8]) -> Result<u32, Error> {
+        #[derive(Deserialize)]
+        struct Version { #[serde(default)] version: u32 }
+
+        Ok(match self {
+            SetFormat::Json => serde_json::from_slice::<Version>(data)?.version,
+            SetFormat::Cbor => serde_cbor::from_slice::<Version>(data)?.version,
+        })
+    }
+
+    /// Load a set from the contents of a set file in this format. Sets from this version or a newer one are read
+    /// straight into their saved form, and older ones into a document first, since that's what the migrations work on.
+    fn read(self, data: &[u8]) -> Result<(BackgroundSet, Vec<SetLoadWarning>), Error> {
+        let version = self.version(data)?;
+        if version < SET_FORMAT_VERSION {
+            let document = match self {
+                SetFormat::Json => serde_json::from_slice(data)?,
+                SetFormat::Cbor => serde_cbor::from_slice(data)?,
+            };
+            return BackgroundSet::from_document(document)
+        }
+
+        let mut saved_data: SavedBackgroundSet = match self {
+            SetFormat::Json => serde_json::from_slice(data)?,
+            SetFormat::Cbor => serde_cbor::from_slice(data)?,
         };
-        serde_json::to_writer(writer, &saved_data).map_err(Into::into)
+        migrate::upgrade_sources(&mut saved_data.sources)?;
+        let mut warnings = Vec::new();
+        if version > SET_FORMAT_VERSION { warnings.push(SetLoadWarning::NewerVersion { version }); }
+        let (set, load_warnings) = saved_data.load();
+        warnings.extend(load_warnings);
+        Ok((set, warnings))
+    }
+
+    fn write(self, writer: &mut dyn Write, value: &impl Serialize) -> Result<(), Error> {
+        match self {
+            SetFormat::Json => serde_json::to_writer(writer, value)?,
+            SetFormat::Cbor => {
+                let mut serializer = serde_cbor::Serializer::new(serde_cbor::ser::IoWrite::new(writer));
+                serializer.self_describe()?;
+                value.serialize(&mut serializer)?;
+            }
+        }
+        Ok(())
     }
 }
 
@@ -47,6 +193,11 @@ impl BackgroundSet {
 pub enum Error {
     Io(std::io::Error),
     Json(serde_json::Error),
+    Cbor(serde_cbor::Error),
+    /// Someone else holds the lock on the set file. They are unknown if the lock file couldn't be read.
+    Locked(Option<LockHolder>),
+    /// The set file was saved by a newer version, in this format version, so it can't be saved over.
+    NewerVersion(u32),
 }
 
 impl From<std::io::Error> for Error {
@@ -61,38 +212,108 @@ impl From<serde_json::Error> for Error {
     }
 }
 
-#[derive(Debug, Serialize, Deserialize)]
+impl From<serde_cbor::Error> for Error {
+    fn from(error: serde_cbor::Error) -> Error {
+        Error::Cbor(error)
+    }
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
 pub struct SavedBackgroundSource {
-    ty: String,
-    data: serde_json::Value,
-    backgrounds: Vec<SavedDesktopBackground>,
+    pub(super) ty: String,
+    pub(super) uuid: Uuid,
+    pub(super) version: u32,
+    pub(super) data: serde_json::Value,
+    pub(super) backgrounds: Vec<SavedDesktopBackground>,
 }
 
 #[derive(Serialize, Deserialize)]
 pub struct SavedBackgroundSet {
-    image_folder: PathBuf,
-    name: String,
-    resolution: (usize, usize),
-    sources: Vec<SavedBackgroundSource>,
+    pub(super) version: u32,
+    #[serde(with = "crate::utils::portable_path::option")]
+    pub(super) image_folder: Option<PathBuf>, // Only missing from recovery files, since sets can't be saved without these
+    pub(super) name: Option<String>,
+    pub(super) resolution: (usize, usize),
+    #[serde(default = "default_backup_count")]
+    pub(super) backup_count: usize,
+    #[serde(default)]
+    pub(super) relative_paths: bool,
+    #[serde(default)]
+    pub(super) resize_filter: ResizeFilter,
+    #[serde(default)]
+    pub(super) output_settings: OutputSettings,
+    pub(super) sources: Vec<SavedBackgroundSource>,
+}
+
+fn default_backup_count() -> usize { backup::DEFAULT_BACKUP_COUNT }
+
+/// A source which couldn't be loaded, perhaps because it was saved by a newer version. It is kept exactly as it
+/// was saved, along with its backgrounds, so that saving the set again doesn't lose anything.
+#[derive(Debug)]
+pub struct UnloadedSource {
+    saved: SavedBackgroundSource,
+    pub error: sources::SourceLoadError,
+}
+
+impl UnloadedSource {
+    /// The source's name, if it can be found in its saved data.
+    pub fn name(&self) -> Option<&str> {
+        self.saved.data.get("name").and_then(serde_json::Value::as_str)
+    }
+
+    pub fn source_type_id(&self) -> &str {
+        &self.saved.ty
+    }
+
+    pub fn background_count(&self) -> usize {
+        self.saved.backgrounds.len()
+    }
+
+    /// A human-readable explanation of why the source couldn't be loaded.
+    pub fn describe_error(&self) -> String {
+        describe_load_error(&self.saved.ty, &self.error)
+    }
+}
+
+pub(super) fn describe_load_error(ty: &str, error: &sources::SourceLoadError) -> String {
+    match error {
+        sources::SourceLoadError::IdNotFound => format!("Unknown source type \"{}\".", ty),
+        sources::SourceLoadError::Deserialize(e) => format!("The source's data is invalid: {}", e),
+        sources::SourceLoadError::NewerVersion { .. } => "The source was saved by a newer version of this program.".to_owned(),
+    }
 }
 
 #[derive(Debug)]
 pub enum SetLoadWarning {
-    CorruptSource { source: SavedBackgroundSource, error: sources::SourceLoadError },
-    // CorruptOriginalKey { background: SavedDesktopBackground }, 
+    /// The source at this index of `BackgroundSet::unloaded_sources` could not be loaded.
+    CorruptSource { index: usize },
+    /// The set was saved by a newer version, so some of it may not have been understood.
+    NewerVersion { version: u32 },
+    /// The key of the background with this ID is corrupt, so its original can't be found. `SavedBackgroundSet::check`
+    /// can find and repair backgrounds like this.
+    CorruptOriginalKey { background: usize, error: serde_json::Error },
 }
 
 impl SavedBackgroundSet {
-    fn load(self) -> (BackgroundSet, Vec<SetLoadWarning>) {
+    pub fn load(self) -> (BackgroundSet, Vec<SetLoadWarning>) {
         let mut warnings = Vec::new();
+        let mut unloaded_sources = Vec::new();
         let mut sources = StableVec::new();
+        let mut source_uuids = HashMap::new();
         let mut backgrounds = StableVec::new();
         for saved_source in self.sources {
-            match sources::load_source_by_id(&saved_source.ty, saved_source.data.clone()) {
+            let supported = sources::source_version(&saved_source.ty);
+            let loaded = match saved_source.version > supported {
+                true => Err(sources::SourceLoadError::NewerVersion { version: saved_source.version, supported }),
+                false => sources::load_source_by_id(&saved_source.ty, saved_source.data.clone()),
+            };
+            match loaded {
                 Ok(source) => {
-                    backgrounds.extend(saved_source.backgrounds.into_iter().map(|b| {
+                    for b in saved_source.backgrounds {
+                        let key_error = source.check_key(&b.key_data).err();
                         let key = source.assemble_key(b.key_data);
-                        DesktopBackground {
+                        let id = backgrounds.push(DesktopBackground {
+                            uuid: b.uuid,
                             name: b.name,
                             location: b.location,
                             comments: b.comments,
@@ -107,35 +328,130 @@ impl SavedBackgroundSet {
                                 _ => OriginalMeta::Unavailable { last_known_size: b.original_meta.last_known_size },
                             },
                             original: key,
+                        });
+                        if let Some(error) = key_error {
+                            warnings.push(SetLoadWarning::CorruptOriginalKey { background: id, error });
                         }
-                    }));
-                    sources.push(source);
+                    }
+                    source_uuids.insert(sources.push(source), saved_source.uuid);
+                }
+                Err(error) => {
+                    warnings.push(SetLoadWarning::CorruptSource { index: unloaded_sources.len() });
+                    unloaded_sources.push(UnloadedSource { saved: saved_source, error });
                 }
-                Err(error) => warnings.push(SetLoadWarning::CorruptSource {
-                    source: saved_source, error
-                })
             }
         }
         (BackgroundSet {
-            image_folder: Some(self.image_folder),
-            name: Some(self.name),
+            image_folder: self.image_folder,
+            name: self.name,
             resolution: self.resolution,
+            backup_count: self.backup_count,
+            relative_paths: self.relative_paths,
+            resize_filter: self.resize_filter,
+            output_settings: self.output_settings,
             backgrounds,
             sources,
+            source_uuids,
+            unloaded_sources,
+            lock: None,
+            newer_file: None,
         }, warnings)
     }
 }
 
-#[derive(Debug, Serialize, Deserialize)]
-struct SavedOriginalMeta { last_known_size: Option<(u32, u32)> }
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub(super) struct SavedOriginalMeta { pub(super) last_known_size: Option<(u32, u32)> }
 
-#[derive(Debug, Serialize, Deserialize)]
+#[derive(Clone, Debug, Serialize, Deserialize)]
 pub struct SavedDesktopBackground {
-    name: String,
-    location: String,
-    comments: String,
-    key_data: serde_json::Value,
-    flags: DesktopBackgroundFlags,
-    original_meta: SavedOriginalMeta,
-    edit_info: Option<EditInfo>,
-}
\ No newline at end of file
+    pub(super) uuid: Uuid,
+    pub(super) name: String,
+    pub(super) location: String,
+    pub(super) comments: String,
+    pub(super) key_data: serde_json::Value,
+    pub(super) flags: DesktopBackgroundFlags,
+    pub(super) original_meta: SavedOriginalMeta,
+    pub(super) edit_info: Option<EditInfo>,
+}
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+    use super::*;
+
+    /// A set as the first version of the program saved it, before the format had a version.
+    fn baseline_document() -> serde_json::Value {
+        let hash = vec![7u8; 32];
+        json!({
+            "image_folder": "/walls/out",
+            "name": "Walls",
+            "resolution": [1920, 1080],
+            "sources": [{
+                "ty": "folder",
+                "data": {
+                    "folder": "/walls",
+                    "name": "Walls",
+                    "originals": [[{ "Unix": b"a.png" }, { "mismatch": false, "path": "/walls/a.png", "hash": hash }]],
+                },
+                "backgrounds": [{
+                    "name": "a.png",
+                    "location": "/walls/a.png",
+                    "comments": "",
+                    "key_data": { "filename": { "Unix": b"a.png" }, "hash": hash },
+                    "flags": { "bits": 1 },
+                    "original_meta": { "last_known_size": [4, 4] },
+                    "edit_info": { "center": { "x": 2.0, "y": 2.0 }, "scale": 1.0 },
+                }],
+            }],
+        })
+    }
+
+    #[test]
+    fn baseline_document_upgrades_through_every_version() {
+        let mut document = baseline_document();
+        assert!(migrate::upgrade(&mut document).is_empty());
+        assert_eq!(document["version"], SET_FORMAT_VERSION);
+        let source = &document["sources"][0];
+        assert_eq!(source["version"], sources::source_version("folder"));
+        assert!(source["uuid"].is_string() && source["backgrounds"][0]["uuid"].is_string());
+
+        let (set, warnings) = BackgroundSet::from_document(baseline_document()).unwrap();
+        assert!(warnings.is_empty(), "{:?}", warnings);
+        let background = set.backgrounds.values().next().unwrap();
+        assert_eq!(background.name, "a.png");
+        assert_eq!(background.flags.bits(), 1);
+        assert!(background.edit_info.is_some());
+        match set.sources[background.source].original(&background.original) {
+            OriginalResult::Original(original) => assert_eq!(original.location(), "/walls/a.png"),
+            _ => panic!("The background's key doesn't match its original after upgrading."),
+        }
+    }
+
+    #[test]
+    fn json_and_cbor_files_load_the_same_set() {
+        let (set, _) = BackgroundSet::from_document(baseline_document()).unwrap();
+        let saved = set.to_saved();
+        let load = |format: SetFormat| {
+            let mut data = Vec::new();
+            format.write(&mut data, &saved).unwrap();
+            assert_eq!(SetFormat::detect(&data), format);
+            let (set, warnings) = SetFormat::detect(&data).read(&data).unwrap();
+            assert!(warnings.is_empty(), "{:?}", warnings);
+            serde_json::to_value(set.to_saved()).unwrap()
+        };
+        assert_eq!(load(SetFormat::Json), load(SetFormat::Cbor));
+        assert_eq!(load(SetFormat::Json), serde_json::to_value(&saved).unwrap());
+    }
+
+    #[test]
+    fn older_encodings_load_the_same_set_from_json_and_cbor() {
+        // Hashes as lists of numbers and UUIDs as strings, as version 2 wrote them.
+        let mut document = baseline_document();
+        migrate::upgrade(&mut document);
+        document["version"] = 2.into();
+        let json = serde_json::to_vec(&document).unwrap();
+        let mut cbor = Vec::new();
+        SetFormat::Cbor.write(&mut cbor, &document).unwrap();
+        let load = |data: &[u8]| serde_json::to_value(SetFormat::detect(data).read(data).unwrap().0.to_saved()).unwrap();
+        assert_eq!(load(&json), load(&cbor));
+    }
+}
@@ -0,0 +1,293 @@
+use std::ffi::OsString;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::sync::mpsc::{self, Receiver, TryRecvError};
+use std::thread::{self, JoinHandle};
+
+use rayon::prelude::*;
+
+use crate::background::{BackgroundSet, DesktopBackgroundFlags, CropRegion, Original, OriginalMeta, ResizeFilter, OutputSettings};
+use super::manifest::{self, Manifest, ManifestEntry};
+
+#[derive(Debug)]
+pub enum SkipReason {
+    OriginalUnavailable,
+    CorruptImage(image::ImageError),
+    Excluded,
+}
+
+#[derive(Debug)]
+pub enum RebuildError {
+    Io(io::Error),
+    /// The image folder has files in it, but wasn't built by dbgm. Rebuilding into it has to be confirmed.
+    UnownedFolder,
+    /// The rebuild was cancelled before all the new outputs were moved in, so the image folder is unchanged.
+    Cancelled,
+}
+
+impl From<io::Error> for RebuildError {
+    fn from(error: io::Error) -> RebuildError {
+        RebuildError::Io(error)
+    }
+}
+
+/// A rebuild of the image folder running in the background. Dropping it cancels the rebuild and waits for it to stop,
+/// so that it's never cut short while moving outputs into the image folder.
+pub struct Rebuild {
+    progress: Arc<Progress>,
+    total: usize,
+    result: Receiver<Result<Vec<(usize, SkipReason)>, RebuildError>>,
+    thread: Option<JoinHandle<()>>,
+}
+
+struct Progress {
+    done: AtomicUsize,
+    current: Mutex<Option<String>>, // The name of the background most recently started
+    cancelled: AtomicBool,
+}
+
+impl Rebuild {
+    /// How many of the backgrounds which needed rendering have been done, and how many there are.
+    pub fn progress(&self) -> (usize, usize) {
+        (self.progress.done.load(Ordering::Relaxed), self.total)
+    }
+
+    /// The name of the background which was most recently started.
+    pub fn current(&self) -> Option<String> {
+        self.progress.current.lock().unwrap().clone()
+    }
+
+    /// Stop rendering and leave the image folder as it was. Outputs which were already moved in are taken out again.
+    pub fn cancel(&self) {
+        self.progress.cancelled.store(true, Ordering::Relaxed);
+    }
+
+    pub fn is_cancelled(&self) -> bool {
+        self.progress.cancelled.load(Ordering::Relaxed)
+    }
+
+    /// Returns the result of the rebuild if it has finished: a list of background IDs that were *not* included.
+    /// `set` should be the set the rebuild was started from; backgrounds whose originals couldn't be read have their
+    /// metadata reloaded in it.
+    pub fn poll(&self, set: &mut BackgroundSet) -> Option<Result<Vec<(usize, SkipReason)>, RebuildError>> {
+        let result = match self.result.try_recv() {
+            Ok(result) => result,
+            Err(TryRecvError::Empty) => return None,
+            Err(TryRecvError::Disconnected) => Err(stopped_unexpectedly()),
+        };
+        Some(result.map(|skipped| { set.reload_unreadable(&skipped); skipped }))
+    }
+}
+
+impl Drop for Rebuild {
+    fn drop(&mut self) {
+        self.cancel();
+        if let Some(thread) = self.thread.take() { let _ = thread.join(); }
+    }
+}
+
+fn stopped_unexpectedly() -> RebuildError {
+    io::Error::new(io::ErrorKind::Other, "The rebuild stopped unexpectedly.").into()
+}
+
+impl BackgroundSet {
+    /// Starts bringing the image folder up to date with the set. Only outputs which are new or changed since the last
+    /// rebuild are rendered, as recorded in the folder's manifest, and only outputs listed there are ever removed.
+    /// Rendering is spread across the thread pool, into a staging folder next to the image folder. The new outputs
+    /// are only moved into the image folder once they've all been rendered, so a failed or cancelled rebuild leaves it
+    /// as it was.
+    ///
+    /// A folder without a manifest which has anything in it besides outputs from before manifests existed is only
+    /// rebuilt into if `adopt_folder` is set; its files are kept.
+    pub fn start_rebuild(&self, adopt_folder: bool) -> Result<Rebuild, RebuildError> {
+        let image_folder = self.image_folder.clone().expect("Cannot update image folder when none is set!");
+        let staging = staging_folder(&image_folder);
+
+        // Ensure the image folder exists, and that it's ours to write to.
+        fs::create_dir_all(&image_folder)?;
+        let old_manifest = match Manifest::load(&image_folder) {
+            Some(manifest) => manifest,
+            None => {
+                let (legacy, foreign_files) = Manifest::find_legacy(&image_folder)?;
+                if foreign_files && !adopt_folder { return Err(RebuildError::UnownedFolder) }
+                legacy
+            }
+        };
+
+        // Work out what needs rendering up front, since the set itself can't be shared with other threads.
+        let mut plan = Plan {
+            image_folder, staging, old_manifest,
+            jobs: Vec::new(),
+            manifest: Manifest::default(),
+            skipped: Vec::new(),
+            filter: self.resize_filter,
+            settings: self.output_settings.clone(),
+        };
+        for (id, background) in self.backgrounds.iter().filter(|(_, b)| !b.flags.contains(DesktopBackgroundFlags::EXCLUDED)) {
+            let original = match self.sources[background.source].original(&background.original).as_option() {
+                Some(original) => original,
+                None => { plan.skipped.push((id, SkipReason::OriginalUnavailable)); continue }
+            };
+
+            let resolution = vec2![self.resolution.0 as f32, self.resolution.1 as f32];
+            let crop_region = match background.crop_region(resolution) {
+                Ok(crop_region) => crop_region,
+                Err(_) => { plan.skipped.push((id, SkipReason::OriginalUnavailable)); continue }
+            };
+
+            let fingerprint = manifest::fingerprint(background, &crop_region, self.resolution, self.resize_filter, &self.output_settings.encoder_id());
+            let name = format!("{}.{}", fingerprint, self.output_settings.format.extension());
+            if plan.manifest.outputs.contains_key(&name) {
+                // Another background has exactly the same output
+            } else if plan.old_manifest.outputs.get(&name).map_or(false, |entry| entry.fingerprint == fingerprint) && plan.image_folder.join(&name).is_file() {
+                // Already up to date in the image folder
+            } else {
+                plan.jobs.push(Job { id, name: background.name.clone(), original: original.boxed_clone(), crop_region, output: name.clone() });
+            }
+            plan.manifest.outputs.insert(name, ManifestEntry { background: background.uuid, fingerprint });
+        }
+
+        // Only dbgm writes to the staging folder, so anything left in it by an earlier rebuild can go.
+        if plan.staging.exists() { fs::remove_dir_all(&plan.staging)?; }
+        fs::create_dir(&plan.staging)?;
+
+        let progress = Arc::new(Progress { done: AtomicUsize::new(0), current: Mutex::new(None), cancelled: AtomicBool::new(false) });
+        let (sender, receiver) = mpsc::channel();
+        let (total, thread_progress) = (plan.jobs.len(), progress.clone());
+        let thread = thread::spawn(move || { let _ = sender.send(plan.run(&thread_progress)); });
+        Ok(Rebuild { progress, total, result: receiver, thread: Some(thread) })
+    }
+
+    /// Reload the metadata of backgrounds which were skipped because their originals couldn't be read.
+    fn reload_unreadable(&mut self, skipped: &[(usize, SkipReason)]) {
+        for (id, reason) in skipped {
+            if let SkipReason::CorruptImage(_) = reason {} else { continue }
+            let background = match self.backgrounds.get_mut(*id) { Some(background) => background, None => continue };
+            if let Some(original) = self.sources.get(background.source).and_then(|s| s.original(&background.original).as_option()) {
+                background.original_meta = OriginalMeta::load(original, Some(&background.original_meta));
+            }
+        }
+    }
+}
+
+/// Everything a rebuild needs once it has left the set behind.
+struct Plan {
+    image_folder: PathBuf,
+    staging: PathBuf,
+    old_manifest: Manifest,
+    jobs: Vec<Job>,
+    manifest: Manifest,
+    skipped: Vec<(usize, SkipReason)>,
+    filter: ResizeFilter,
+    settings: OutputSettings,
+}
+
+/// A background whose output has to be rendered.
+struct Job {
+    id: usize,
+    name: String,
+    original: Box<dyn Original + Send + Sync>,
+    crop_region: CropRegion,
+    output: String,
+}
+
+impl Plan {
+    fn run(mut self, progress: &Progress) -> Result<Vec<(usize, SkipReason)>, RebuildError> {
+        let result = self.render(progress).and_then(|_| self.move_in(progress));
+        let _ = fs::remove_dir_all(&self.staging);
+        result.map(|_| self.skipped)
+    }
+
+    /// Render every output which isn't up to date into the staging folder.
+    fn render(&mut self, progress: &Progress) -> Result<(), RebuildError> {
+        let (staging, filter, settings) = (&self.staging, self.filter, &self.settings);
+        let errors = self.jobs.par_iter().map(|job| {
+            if progress.cancelled.load(Ordering::Relaxed) { return Err(RebuildError::Cancelled) }
+            *progress.current.lock().unwrap() = Some(job.name.clone());
+            let result = job.render(staging, filter, settings);
+            progress.done.fetch_add(1, Ordering::Relaxed);
+            result
+        }).collect::<Result<Vec<_>, _>>()?;
+
+        for (job, error) in self.jobs.iter().zip(errors) {
+            if let Some(error) = error {
+                self.manifest.outputs.remove(&job.output);
+                self.skipped.push((job.id, SkipReason::CorruptImage(error)));
+            }
+        }
+        Ok(())
+    }
+
+    /// Move the rendered outputs into the image folder one by one, write the new manifest, and remove the stale
+    /// outputs. Nothing else in the folder is touched. If an output can't be moved in, or the rebuild is cancelled
+    /// meanwhile, the ones already moved in are taken out again, so the folder and its manifest are left as they were.
+    fn move_in(&mut self, progress: &Progress) -> Result<(), RebuildError> {
+        let mut moved = Vec::new();
+        // Once the manifest lists the new outputs, the rebuild can't be undone. It keeps listing the stale ones until
+        // they're removed, so that if this is interrupted the next rebuild can still tell which files are dbgm's.
+        let result = self.move_outputs(progress, &mut moved).and_then(|_| {
+            let mut claimed = Manifest { outputs: self.old_manifest.outputs.clone() };
+            claimed.outputs.extend(self.manifest.outputs.iter().map(|(name, entry)| (name.clone(), entry.clone())));
+            Ok(claimed.save(&self.image_folder)?)
+        });
+        if let Err(e) = result {
+            // No manifest lists these yet, so nothing else would ever remove them.
+            for path in moved { let _ = fs::remove_file(path); }
+            return Err(e)
+        }
+
+        for (name, entry) in &self.old_manifest.outputs {
+            if self.manifest.outputs.contains_key(name) { continue }
+            match fs::remove_file(self.image_folder.join(name)) {
+                Err(ref e) if e.kind() != io::ErrorKind::NotFound => {
+                    // Probably in use. Keep it in the manifest, so that the next rebuild tries again.
+                    self.manifest.outputs.insert(name.clone(), entry.clone());
+                },
+                _ => {},
+            }
+        }
+        self.manifest.save(&self.image_folder)?;
+        Ok(())
+    }
+
+    /// Move the rendered outputs into the image folder, adding the paths of those which weren't there already to
+    /// `moved`. Cancelling the rebuild stops this between outputs.
+    fn move_outputs(&self, progress: &Progress, moved: &mut Vec<PathBuf>) -> Result<(), RebuildError> {
+        for job in self.jobs.iter().filter(|job| self.manifest.outputs.contains_key(&job.output)) {
+            if progress.cancelled.load(Ordering::Relaxed) { return Err(RebuildError::Cancelled) }
+            // A file which is already there has the same name, so it's the same output, and it's kept if this is undone.
+            let target = self.image_folder.join(&job.output);
+            let existed = target.exists();
+            fs::rename(self.staging.join(&job.output), &target)?;
+            if !existed { moved.push(target); }
+        }
+        match progress.cancelled.load(Ordering::Relaxed) {
+            true => Err(RebuildError::Cancelled),
+            false => Ok(()),
+        }
+    }
+}
+
+impl Job {
+    /// Render the output into `staging`. Returns the error if the original couldn't be read.
+    fn render(&self, staging: &Path, filter: ResizeFilter, settings: &OutputSettings) -> Result<Option<image::ImageError>, RebuildError> {
+        let image = match self.original.read_image() {
+            Ok(image) => image,
+            Err(e) => return Ok(Some(e)),
+        };
+        let cropped = self.crop_region.crop(&image, filter);
+        fs::write(staging.join(&self.output), settings.encode(&cropped)?)?;
+        Ok(None)
+    }
+}
+
+/// A hidden folder next to the image folder, which new outputs are rendered into. Only dbgm writes to it.
+fn staging_folder(image_folder: &Path) -> PathBuf {
+    let mut name = OsString::from(".");
+    name.push(image_folder.file_name().unwrap_or_default());
+    name.push(".dbgm-rebuild");
+    image_folder.with_file_name(name)
+}
@@ -0,0 +1,147 @@
+use std::path::{Path, PathBuf};
+use std::time::SystemTime;
+use std::fs::{self, File};
+use std::io::{self, BufReader, ErrorKind};
+
+use blake2::{VarBlake2b, digest::{Input, VariableOutput}};
+use serde::{Serialize, Deserialize};
+
+use crate::background::{BackgroundSet, SetLoadWarning, SetLock};
+use crate::background::persist::{Error, SavedBackgroundSet};
+
+/// The folder holding the recovery files of every running or crashed instance.
+fn recovery_folder() -> PathBuf {
+    dirs::data_local_dir().unwrap_or_else(std::env::temp_dir).join("dbgm").join("recovery")
+}
+
+/// Where this process autosaves the set belonging to `set_path`, so that unsaved work can be recovered if it crashes.
+/// The name depends on the set file and the process, so that instances editing different sets, or the same one,
+/// never overwrite or discard each other's work.
+pub fn recovery_path(set_path: Option<&Path>) -> PathBuf {
+    let set = match set_path {
+        Some(path) => {
+            let mut hasher = VarBlake2b::new(12).unwrap();
+            hasher.input(path.to_string_lossy().as_bytes());
+            let mut name = String::new();
+            hasher.variable_result(|hash| name = base64::encode_config(hash, base64::URL_SAFE));
+            name
+        },
+        None => "unsaved".to_owned(),
+    };
+    recovery_folder().join(format!("{}-{}.bgs", std::process::id(), set))
+}
+
+fn remove_recovery_file(path: &Path) -> io::Result<()> {
+    match fs::remove_file(path) {
+        Err(e) if e.kind() != ErrorKind::NotFound => Err(e),
+        _ => Ok(()),
+    }
+}
+
+/// Whether a running instance owns the recovery file at `path`, so no other instance should offer or delete it.
+fn is_owned(path: &Path) -> bool {
+    SetLock::holder(path).map_or(false, |holder| holder.is_running())
+}
+
+/// Lock a recovery file which no running instance owns, e.g. because the one which wrote it crashed.
+fn claim_unowned(path: &Path) -> Result<SetLock, Error> {
+    match SetLock::holder(path) {
+        Some(holder) if holder.is_running() => Err(Error::Locked(Some(holder))),
+        _ => SetLock::acquire(path, true),
+    }
+}
+
+/// A recovery file belonging to this instance. It's locked for as long as this exists, so that other instances know
+/// it's still in use, and don't offer to recover or discard it.
+pub struct RecoveryFile {
+    path: PathBuf,
+    _lock: SetLock,
+}
+
+impl RecoveryFile {
+    /// Take the recovery file this instance should autosave the set belonging to `set_path` to.
+    pub fn claim(set_path: Option<&Path>) -> Result<RecoveryFile, Error> {
+        let path = recovery_path(set_path);
+        fs::create_dir_all(recovery_folder())?;
+        // The name is unique to this process, so any lock on it was left by an earlier process with the same ID.
+        let lock = SetLock::acquire(&path, true)?;
+        Ok(RecoveryFile { path, _lock: lock })
+    }
+
+    /// Whether this is the file that the set belonging to `set_path` should be autosaved to.
+    pub fn belongs_to(&self, set_path: Option<&Path>) -> bool {
+        self.path == recovery_path(set_path)
+    }
+
+    /// Delete the file, e.g. once its contents have been saved or the user has chosen to discard them.
+    pub fn remove(self) -> io::Result<()> {
+        // The lock is only released afterwards, so no other instance can find the file unowned in the meantime.
+        remove_recovery_file(&self.path)
+    }
+}
+
+#[derive(Serialize)]
+struct SavedRecovery<'a> {
+    set_path: Option<&'a Path>,
+    time: SystemTime,
+    set: SavedBackgroundSet,
+}
+
+/// Unsaved work found in a recovery file. The set itself isn't loaded until `load` is called.
+#[derive(Deserialize)]
+pub struct Recovery {
+    #[serde(skip)]
+    file: PathBuf,
+    /// The file the set was last loaded from or saved to, if any.
+    pub set_path: Option<PathBuf>,
+    /// When the set was autosaved.
+    pub time: SystemTime,
+    set: serde_json::Value,
+}
+
+impl Recovery {
+    /// Look for the most recent recovery file holding work which is newer than the set file it belongs to, and which
+    /// no running instance owns.
+    pub fn find() -> Option<Recovery> {
+        let files = fs::read_dir(recovery_folder()).ok()?;
+        files.filter_map(|entry| Some(entry.ok()?.path()))
+            .filter(|path| path.extension().map_or(false, |e| e == "bgs") && !is_owned(path))
+            .filter_map(Recovery::read)
+            .max_by_key(|recovery| recovery.time)
+    }
+
+    fn read(file: PathBuf) -> Option<Recovery> {
+        let mut recovery: Recovery = serde_json::from_reader(BufReader::new(File::open(&file).ok()?)).ok()?;
+        recovery.file = file;
+        let set_modified = recovery.set_path.as_ref().and_then(|p| fs::metadata(p).and_then(|m| m.modified()).ok());
+        match set_modified {
+            Some(modified) if modified >= recovery.time => None,
+            _ => Some(recovery),
+        }
+    }
+
+    /// Take over the recovery file this was found in, to autosave the recovered set to until it's saved. This fails
+    /// with `Error::Locked` if another instance has taken it since it was found.
+    pub fn claim(&self) -> Result<RecoveryFile, Error> {
+        Ok(RecoveryFile { path: self.file.clone(), _lock: claim_unowned(&self.file)? })
+    }
+
+    pub fn load(self) -> Result<(BackgroundSet, Vec<SetLoadWarning>), Error> {
+        BackgroundSet::from_document(self.set)
+    }
+
+    /// Delete the recovery file this was found in, because the user doesn't want it. This fails with `Error::Locked`
+    /// if another instance has taken it since it was found.
+    pub fn discard(self) -> Result<(), Error> {
+        let _lock = claim_unowned(&self.file)?;
+        Ok(remove_recovery_file(&self.file)?)
+    }
+}
+
+impl BackgroundSet {
+    /// Autosave the set to a recovery file. `set_path` is the file the set belongs to, if it has one.
+    pub fn save_recovery(&self, set_path: Option<&Path>, file: &RecoveryFile) -> Result<(), Error> {
+        let saved = SavedRecovery { set_path, time: SystemTime::now(), set: self.to_saved() };
+        crate::utils::write_atomically(&file.path, |writer| serde_json::to_writer(writer, &saved).map_err(Error::from))
+    }
+}
@@ -0,0 +1,144 @@
+use std::collections::VecDeque;
+
+use image::{DynamicImage, GenericImageView, RgbaImage, Pixel};
+use serde::{Serialize, Deserialize};
+
+use crate::math::Vec2;
+
+/// The filter used to scale a background's crop region to the set's resolution.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub enum ResizeFilter {
+    Nearest,
+    Triangle,
+    CatmullRom,
+    Gaussian,
+    Lanczos3,
+}
+
+impl Default for ResizeFilter {
+    fn default() -> ResizeFilter { ResizeFilter::Lanczos3 }
+}
+
+impl ResizeFilter {
+    pub const ALL: [ResizeFilter; 5] = [
+        ResizeFilter::Nearest, ResizeFilter::Triangle, ResizeFilter::CatmullRom, ResizeFilter::Gaussian, ResizeFilter::Lanczos3
+    ];
+
+    pub fn name(self) -> &'static str {
+        match self {
+            ResizeFilter::Nearest => "Nearest",
+            ResizeFilter::Triangle => "Triangle",
+            ResizeFilter::CatmullRom => "Catmull-Rom",
+            ResizeFilter::Gaussian => "Gaussian",
+            ResizeFilter::Lanczos3 => "Lanczos3",
+        }
+    }
+
+    /// How far the kernel extends either side of a sample, in output pixels.
+    fn support(self) -> f32 {
+        match self {
+            ResizeFilter::Nearest => 0.5,
+            ResizeFilter::Triangle => 1.0,
+            ResizeFilter::CatmullRom => 2.0,
+            ResizeFilter::Gaussian | ResizeFilter::Lanczos3 => 3.0,
+        }
+    }
+
+    // The kernels are the same as those `image::imageops::resize` uses.
+    fn kernel(self, x: f32) -> f32 {
+        match self {
+            ResizeFilter::Nearest => if x.abs() <= 0.5 { 1.0 } else { 0.0 },
+            ResizeFilter::Triangle => f32::max(0.0, 1.0 - x.abs()),
+            ResizeFilter::CatmullRom => bc_cubic_spline(x, 0.0, 0.5),
+            ResizeFilter::Gaussian => (-x * x / 0.5).exp() / (std::f32::consts::PI / 2.0).sqrt(), // Radius 0.5
+            ResizeFilter::Lanczos3 => if x.abs() < 3.0 { sinc(x) * sinc(x / 3.0) } else { 0.0 },
+        }
+    }
+
+    /// The input pixels which contribute to each output pixel along one axis, with their weights. The input region
+    /// starts at `start` and is `length` pixels long, which needn't be whole numbers.
+    fn weights(self, start: f32, length: f32, input_size: u32, output_size: u32) -> Vec<(u32, Vec<f32>)> {
+        let ratio = length / output_size as f32;
+        let scale = f32::max(ratio, 1.0); // Widen the kernel when downscaling, so that every input pixel counts
+        let support = self.support() * scale;
+        let last = input_size as i64 - 1;
+        (0..output_size).map(|out| {
+            let center = start + (out as f32 + 0.5) * ratio;
+            if self == ResizeFilter::Nearest {
+                return ((center.floor() as i64).max(0).min(last) as u32, vec![1.0])
+            }
+            let left = ((center - support).floor() as i64).max(0).min(last);
+            let right = ((center + support).ceil() as i64).max(left + 1).min(last + 1);
+            let mut weights = (left..right).map(|i| self.kernel((i as f32 + 0.5 - center) / scale)).collect::<Vec<_>>();
+            let sum: f32 = weights.iter().sum();
+            if sum != 0.0 { weights.iter_mut().for_each(|w| *w /= sum); }
+            (left as u32, weights)
+        }).collect()
+    }
+}
+
+fn sinc(x: f32) -> f32 {
+    if x == 0.0 { return 1.0 }
+    let x = x * std::f32::consts::PI;
+    x.sin() / x
+}
+
+fn bc_cubic_spline(x: f32, b: f32, c: f32) -> f32 {
+    let a = x.abs();
+    let k = if a < 1.0 {
+        (12.0 - 9.0 * b - 6.0 * c) * a.powi(3) + (-18.0 + 12.0 * b + 6.0 * c) * a.powi(2) + (6.0 - 2.0 * b)
+    } else if a < 2.0 {
+        (-b - 6.0 * c) * a.powi(3) + (6.0 * b + 30.0 * c) * a.powi(2) + (-12.0 * b - 48.0 * c) * a + (8.0 * b + 24.0 * c)
+    } else {
+        0.0
+    };
+    k / 6.0
+}
+
+/// Scale the region of `image` with the given top left corner and size to exactly `output_size`. The region's bounds
+/// can fall between pixels, so it is sampled exactly rather than being rounded out to whole pixels first.
+///
+/// Input rows are scaled horizontally as the output rows reach them, and dropped once no later output row needs them,
+/// so only a kernel's height of them is held at once however large the image is.
+pub(super) fn resample(image: &DynamicImage, top_left: Vec2, size: Vec2, output_size: (u32, u32), filter: ResizeFilter) -> RgbaImage {
+    let (width, height) = image.dimensions();
+    let columns = filter.weights(top_left.x, size.x, width, output_size.0);
+    let rows = filter.weights(top_left.y, size.y, height, output_size.1);
+
+    let mut output = RgbaImage::new(output_size.0, output_size.1);
+    let mut horizontal: VecDeque<(u32, Vec<[f32; 4]>)> = VecDeque::new(); // Scaled input rows, by input row
+    for (y, (start, weights)) in rows.iter().enumerate() {
+        let end = start + weights.len() as u32;
+        while horizontal.front().map_or(false, |(row, _)| row < start) { horizontal.pop_front(); }
+        let mut next = horizontal.back().map_or(*start, |(row, _)| row + 1);
+        while next < end {
+            horizontal.push_back((next, scale_row(image, next, &columns)));
+            next += 1;
+        }
+
+        for x in 0..output_size.0 as usize {
+            let mut sums = [0.0f32; 4];
+            for ((_, row), weight) in horizontal.iter().zip(weights) {
+                for (sum, channel) in sums.iter_mut().zip(&row[x]) {
+                    *sum += channel * weight;
+                }
+            }
+            let channel = |c: f32| c.round().max(0.0).min(255.0) as u8;
+            output.put_pixel(x as u32, y as u32, image::Rgba([channel(sums[0]), channel(sums[1]), channel(sums[2]), channel(sums[3])]));
+        }
+    }
+    output
+}
+
+/// Scale input row `y` to the output's width.
+fn scale_row(image: &DynamicImage, y: u32, columns: &[(u32, Vec<f32>)]) -> Vec<[f32; 4]> {
+    columns.iter().map(|(start, weights)| {
+        let mut sums = [0.0f32; 4];
+        for (i, weight) in weights.iter().enumerate() {
+            for (sum, channel) in sums.iter_mut().zip(image.get_pixel(start + i as u32, y).channels()) {
+                *sum += *channel as f32 * weight;
+            }
+        }
+        sums
+    }).collect()
+}
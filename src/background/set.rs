@@ -1,17 +1,27 @@
 use std::path::{Path, PathBuf};
+use std::collections::HashMap;
 
 use stable_vec::StableVec;
+use uuid::Uuid;
 
 use crate::sources::{DesktopBackgroundSource, ErasedDesktopBackgroundSource};
-use crate::background::{DesktopBackground, DesktopBackgroundFlags};
+use crate::background::{DesktopBackground, UnloadedSource, SetLock, ResizeFilter, OutputSettings};
 use crate::utils::OptionExt as _;
 
 pub struct BackgroundSet {
     pub(super) image_folder: Option<PathBuf>,
     pub(super) name: Option<String>,
     pub(crate) resolution: (usize, usize),
+    pub(super) backup_count: usize,
+    pub(super) relative_paths: bool,
+    pub(super) resize_filter: ResizeFilter,
+    pub(super) output_settings: OutputSettings,
     pub(crate) backgrounds: StableVec<DesktopBackground>,
     pub(crate) sources: StableVec<Box<dyn ErasedDesktopBackgroundSource>>,
+    pub(super) source_uuids: HashMap<usize, Uuid>, // Keyed by source ID, with an entry for every source
+    pub(super) unloaded_sources: Vec<UnloadedSource>,
+    pub(crate) lock: Option<SetLock>, // The lock on the file the set was loaded from or last saved to, if it holds one
+    pub(super) newer_file: Option<(PathBuf, u32)>, // The file the set was loaded from and its version, if that's newer
 }
 
 impl BackgroundSet {
@@ -20,8 +30,16 @@ impl BackgroundSet {
             image_folder: None,
             name: None,
             resolution: resolution,
+            backup_count: super::backup::DEFAULT_BACKUP_COUNT,
+            relative_paths: false,
+            resize_filter: ResizeFilter::default(),
+            output_settings: OutputSettings::default(),
             backgrounds: StableVec::new(),
             sources: StableVec::new(),
+            source_uuids: HashMap::new(),
+            unloaded_sources: Vec::new(),
+            lock: None,
+            newer_file: None,
         }
     }
 
@@ -41,71 +59,72 @@ impl BackgroundSet {
         self.name = Some(name.as_ref().to_owned());
     }
 
+    /// How many backups of the set file are kept when it is saved.
+    pub fn backup_count(&self) -> usize {
+        self.backup_count
+    }
+
+    pub fn set_backup_count(&mut self, count: usize) {
+        self.backup_count = count;
+    }
+
+    /// Whether paths inside the folder containing the set file are saved relative to it, so that the set
+    /// still works if the whole folder is moved or synced to another machine.
+    pub fn relative_paths(&self) -> bool {
+        self.relative_paths
+    }
+
+    pub fn set_relative_paths(&mut self, relative: bool) {
+        self.relative_paths = relative;
+    }
+
+    /// The filter used to scale backgrounds to the set's resolution when the image folder is rebuilt.
+    pub fn resize_filter(&self) -> ResizeFilter {
+        self.resize_filter
+    }
+
+    pub fn set_resize_filter(&mut self, filter: ResizeFilter) {
+        self.resize_filter = filter;
+    }
+
+    /// How backgrounds are encoded when the image folder is rebuilt.
+    pub fn output_settings(&self) -> &OutputSettings {
+        &self.output_settings
+    }
+
+    pub fn set_output_settings(&mut self, settings: OutputSettings) {
+        self.output_settings = settings;
+    }
+
+    /// Sources which couldn't be loaded with the set. These are saved back unchanged.
+    pub fn unloaded_sources(&self) -> &[UnloadedSource] {
+        &self.unloaded_sources
+    }
+
     pub fn add_source<S: for<'a> DesktopBackgroundSource<'a> + 'static>(&mut self, source: S) -> usize {
-        self.sources.push(Box::new(source))
+        let id = self.sources.push(Box::new(source));
+        self.source_uuids.insert(id, Uuid::new_v4());
+        id
     }
 
     pub fn remove_source(&mut self, source: usize) {
         self.backgrounds.retain(|b| b.source != source);
         self.sources.remove(source);
+        self.source_uuids.remove(&source);
     }
 
-    /// Rebuilds the image folder from scratch. Returns a list of background IDs that were *not* included.
-    pub fn rebuild_image_folder(&mut self) -> Result<Vec<(usize, SkipReason)>, std::io::Error> {
-        use std::fs;
-        use blake2::{Blake2b, digest::Digest};
-        use image::ImageFormat;
-        
-        let image_folder = self.image_folder.as_ref().expect("Cannot update image folder when none is set!");
-        
-        // Ensure the image folder exists.
-        fs::create_dir_all(image_folder)?;
-
-        // Clear contents of the image folder. If this fails, we abort.
-        for entry in image_folder.read_dir()? {
-            let entry = entry?;
-            if entry.metadata()?.is_file() {
-                fs::remove_file(entry.path())?; 
-            }
-        }
+    /// The persistent identifier of a source, which unlike its ID stays the same when the set is saved and loaded.
+    pub fn source_uuid(&self, source: usize) -> Option<Uuid> {
+        self.source_uuids.get(&source).cloned()
+    }
 
-        // Save a file in the folder for each background whose original is accessible.
-        let mut skipped = Vec::new();
-        for (id, background) in self.backgrounds.iter_mut().filter(|(_, b)| !b.flags.contains(DesktopBackgroundFlags::EXCLUDED)) { 
-            if background.flags.contains(DesktopBackgroundFlags::EXCLUDED) {
-                skipped.push((id, SkipReason::Excluded));
-                continue
-            }
-            
-            let original = match self.sources[background.source].original(&background.original).as_option() {
-                Some(original) => original,
-                None => { skipped.push((id, SkipReason::OriginalUnavailable)); continue }
-            };
-
-            let mut image = match background.try_read_image_from(original) {
-                Ok(image) => image,
-                Err(e) => { skipped.push((id, SkipReason::CorruptImage(e))); continue }
-            };
-
-            let resolution = vec2![self.resolution.0 as f32, self.resolution.1 as f32];
-            let crop_region = match background.crop_region(resolution) {
-                Ok(crop_region) => crop_region,
-                Err(_) => { skipped.push((id, SkipReason::OriginalUnavailable)); continue }
-            };
-
-            let cropped = crop_region.crop(&mut image).to_image();
-            let mut hasher = Blake2b::new();
-            hasher.input(&*cropped);
-            let path = image_folder.join(base64::encode_config(&hasher.result(), base64::URL_SAFE) + ".png");
-            cropped.save_with_format(path, ImageFormat::PNG)?;
-        }
-        Ok(skipped)
+    /// Find the ID of the source with the given persistent identifier.
+    pub fn find_source(&self, uuid: Uuid) -> Option<usize> {
+        self.source_uuids.iter().find(|(_, u)| **u == uuid).map(|(id, _)| *id)
     }
-}
 
-#[derive(Debug)]
-pub enum SkipReason {
-    OriginalUnavailable,
-    CorruptImage(image::ImageError),
-    Excluded,
-}
\ No newline at end of file
+    /// Find the ID of the background with the given persistent identifier.
+    pub fn find_background(&self, uuid: Uuid) -> Option<usize> {
+        self.backgrounds.iter().find(|(_, b)| b.uuid == uuid).map(|(id, _)| id)
+    }
+}
@@ -0,0 +1,72 @@
+use std::collections::HashMap;
+use std::path::PathBuf;
+use std::sync::mpsc::{self, Receiver};
+use std::time::{Duration, Instant};
+
+use notify::{Watcher, RecommendedWatcher, RecursiveMode, DebouncedEvent};
+
+use crate::background::BackgroundSet;
+
+/// How long notify waits to merge repeated events for the same path.
+const NOTIFY_DELAY: Duration = Duration::from_millis(500);
+/// How long a source has to go without any events before it's considered ready to reload. This means
+/// something like copying a batch of files into a folder results in a single reload.
+const QUIET_PERIOD: Duration = Duration::from_millis(2000);
+
+struct WatchedSource {
+    paths: Vec<(PathBuf, bool)>,
+    watcher: Option<(RecommendedWatcher, Receiver<DebouncedEvent>)>, // None if the paths couldn't be watched
+    last_event: Option<Instant>,
+}
+
+impl WatchedSource {
+    fn start(paths: Vec<(PathBuf, bool)>) -> WatchedSource {
+        let watcher = (|| {
+            let (tx, rx) = mpsc::channel();
+            let mut watcher = notify::watcher(tx, NOTIFY_DELAY)?;
+            for (path, recursive) in &paths {
+                let mode = if *recursive { RecursiveMode::Recursive } else { RecursiveMode::NonRecursive };
+                watcher.watch(path, mode)?;
+            }
+            Ok::<_, notify::Error>((watcher, rx))
+        })();
+        WatchedSource { paths, watcher: watcher.ok(), last_event: None }
+    }
+}
+
+/// Watches the sources of a background set which have watching enabled, and decides when they should be reloaded.
+pub struct SourceWatcher {
+    sources: HashMap<usize, WatchedSource>,
+}
+
+impl SourceWatcher {
+    pub fn new() -> SourceWatcher {
+        SourceWatcher { sources: HashMap::new() }
+    }
+
+    /// Start and stop watching sources to match their current settings, and collect any new events.
+    pub fn update(&mut self, set: &BackgroundSet) {
+        self.sources.retain(|&id, watched| match set.sources.get(id) {
+            Some(source) => source.watching() == Some(true) && source.watch_paths() == watched.paths,
+            None => false,
+        });
+        for (id, source) in set.sources.iter() {
+            if source.watching() == Some(true) && !self.sources.contains_key(&id) {
+                self.sources.insert(id, WatchedSource::start(source.watch_paths()));
+            }
+        }
+        for watched in self.sources.values_mut() {
+            if let Some((_, events)) = &watched.watcher {
+                while events.try_recv().is_ok() { watched.last_event = Some(Instant::now()); }
+            }
+        }
+    }
+
+    /// Returns a source which has changed and has been quiet for long enough to be reloaded, if there is one.
+    pub fn take_ready(&mut self) -> Option<usize> {
+        let (&id, watched) = self.sources.iter_mut()
+            .find(|(_, w)| w.last_event.map(|t| t.elapsed() >= QUIET_PERIOD).unwrap_or(false))?;
+        watched.last_event = None;
+        Some(id)
+    }
+}
\ No newline at end of file
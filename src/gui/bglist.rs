@@ -1,6 +1,6 @@
 use std::collections::HashMap;
 use crate::gui::prelude::*;
-use modals::AddFolderSource;
+use modals::{AddFolderSource, AddArchiveSource, AddListSource, AddCommandSource};
 use widgets::*;
 use crate::background::{DesktopBackground, DesktopBackgroundFlags};
 
@@ -52,7 +52,21 @@ impl GuiState {
             if let Some(set) = &self.set {
                 for (source, bgs) in entries.into_iter().filter(|(_, bgs)| !bgs.is_empty()) {
                     let header_pos = ui.cursor_pos();
-                    if ui.collapsing_header(&im_str!("{}###Source{}", set.sources[source].name(), source)).flags(ImGuiTreeNodeFlags::AllowItemOverlap).build() {
+                    let open = ui.collapsing_header(&im_str!("{}###Source{}", set.sources[source].name(), source)).flags(ImGuiTreeNodeFlags::AllowItemOverlap).build();
+                    let menu_id = &im_str!("SourceMenu{}", source);
+                    if ui.is_item_hovered() && ui.is_mouse_clicked(MouseButton::Right) { ui.open_popup(menu_id); }
+                    ui.popup(menu_id, || {
+                        if set.sources[source].root().is_some() && Selectable::new(im_str!("Relocate...")).build(ui) {
+                            ui.close_current_popup();
+                            operation = Some(Operation::RelocateSource(source));
+                        }
+                        if let Some(mut watching) = set.sources[source].watching() {
+                            if ui.checkbox(im_str!("Reload automatically when files change"), &mut watching) {
+                                operation = Some(Operation::SetWatching(source, watching));
+                            }
+                        }
+                    });
+                    if open {
                         for (id, original) in bgs.into_iter() {
                             let imgui_id = &im_str!("##Background{}", id);
                             let cursor_pos = ui.cursor_pos();
@@ -127,7 +141,13 @@ impl GuiState {
                     };
 
                     if toolbar_button(resources.reload_small.id, 1.15) {
-                        operation = Some(Operation::ReloadSource(source));
+                        operation = Some(match ui.io().key_shift {
+                            true => Operation::VerifySource(source),
+                            false => Operation::ReloadSource(source),
+                        });
+                    }
+                    if ui.is_item_hovered() {
+                        ui.tooltip_text("Reload source (hold Shift to re-check every file)");
                     }
 
                     if toolbar_button(resources.blue_x.id, 1.0) {
@@ -137,6 +157,21 @@ impl GuiState {
                     bcol.pop(ui);
                     ui.set_cursor_pos(next_item);
                 }
+
+                // Sources that couldn't be loaded are shown greyed out so it's clear they haven't been lost.
+                for (i, unloaded) in set.unloaded_sources().iter().enumerate() {
+                    let alpha = ui.push_style_var(StyleVar::Alpha(ui.clone_style().alpha * 0.5));
+                    let name = unloaded.name().unwrap_or(unloaded.source_type_id());
+                    let open = ui.collapsing_header(&im_str!("{} (could not be loaded)###UnloadedSource{}", name, i)).build();
+                    if open {
+                        ui.text_wrapped(&im_str!("{}", unloaded.describe_error()));
+                        ui.text_wrapped(&im_str!(
+                            "This source and its {} background(s) will be kept unchanged when the set is saved.",
+                            unloaded.background_count()
+                        ));
+                    }
+                    alpha.pop(ui);
+                }
             }
         });
         operation.map(|op| self.apply(op));
@@ -175,6 +210,18 @@ impl GuiState {
                         ui.close_current_popup();
                         self.open_modal(AddFolderSource::new());
                     }
+                    if Selectable::new(im_str!("From archive...")).build(ui) {
+                        ui.close_current_popup();
+                        self.open_modal(AddArchiveSource::new());
+                    }
+                    if Selectable::new(im_str!("From file list...")).build(ui) {
+                        ui.close_current_popup();
+                        self.open_modal(AddListSource::new());
+                    }
+                    if Selectable::new(im_str!("From command...")).build(ui) {
+                        ui.close_current_popup();
+                        self.open_modal(AddCommandSource::new());
+                    }
                 });
                 bcol.pop(ui);
             });
@@ -1,9 +1,11 @@
 use std::borrow::Cow;
 
-use crate::background::BackgroundSet;
+use glutin::VirtualKeyCode;
+
 use crate::gui::prelude::*;
+use crate::gui::state::CloseAction;
 
-use modals::{ChangeSetInfo, RebuildSuccess, ErrorModal};
+use modals::{ChangeSetInfo, CheckSet, MergeSets};
 
 pub struct Frame<'f, T: ?Sized> {
     pub ui: &'f Ui<'f>,
@@ -11,13 +13,25 @@ pub struct Frame<'f, T: ?Sized> {
     pub resources: &'f GuiResources,
 }
 
-pub fn draw_state<T: Textures + ?Sized>(state: &mut GuiState, frame: Frame<T>) -> bool {
+/// Draw a frame, returning whether the program should keep running. `close_requested` should be set
+/// if the user has tried to close the window, so that they can be asked to save their changes first.
+pub fn draw_state<T: Textures + ?Sized>(state: &mut GuiState, frame: Frame<T>, close_requested: bool) -> bool {
+    if close_requested { state.request_close(CloseAction::Exit); }
     state.draw(frame)
 }
 
 impl GuiState {
     fn draw<T: Textures + ?Sized>(&mut self, frame: Frame<T>) -> bool {
         if self.debug { frame.ui.show_metrics_window(&mut self.debug); }
+        // The winit platform support indexes imgui's key state by virtual key code.
+        if self.modal.is_none() && self.set.is_some() && frame.ui.io().key_ctrl && frame.ui.is_key_pressed(VirtualKeyCode::S as _) {
+            self.save_set();
+        }
+        if self.modal.is_none() {
+            if let Some(action) = self.pending_close.take() { self.request_close(action); }
+        }
+        self.check_watcher();
+        self.check_autosave();
         frame.ui.fullscreen_window(im_str!("Desktop Background Manager"), || {
             self.check_modal(reborrow_frame!(frame));
             let ui = &frame.ui;
@@ -25,7 +39,7 @@ impl GuiState {
             if let Some(set) = &self.set {
                 let name = set.name().unwrap_or("(unnamed set)");
                 let folder = set.image_folder().map(|f| f.to_string_lossy()).unwrap_or(Cow::from("(no image folder)"));
-                let text = im_str!("{} - {}", name, folder);
+                let text = im_str!("{}{}{} - {}", name, if set.dirty { "*" } else { "" }, if set.read_only() { " (read-only)" } else { "" }, folder);
                 ui.center_avail_h(ui.calc_text_size(&text, false, -1.0)[0]);
                 ui.text(&text);
                 ui.separator();
@@ -48,49 +62,41 @@ impl GuiState {
             ui.next_column();
             self.draw_background_list(frame);
         });
-        true
+        !self.exiting
     }
 
     fn draw_menu_bar(&mut self, ui: &Ui) {
         ui.menu(im_str!("File"), true, || {
             if MenuItem::new(im_str!("New background set...")).build(ui) {
-                self.open_background_set(BackgroundSet::new(crate::utils::primary_monitor_resolution()));
+                self.request_close(CloseAction::NewSet);
             }
             if MenuItem::new(im_str!("Open background set...")).build(ui) {
-                match utils::nfd_handler(nfd::open_file_dialog(Some("bgs"), None), "background set") {
-                    Ok(Some(path)) => match BackgroundSet::load(&path) {
-                        Ok((set, warnings)) => self.open_background_set(set),
-                        Err(e) => {
-                            let msg = format!("The background set at {} could not be loaded.", path.to_string_lossy());
-                            self.open_modal(ErrorModal::new(msg, Some(e)));
-                        }
-                    }
-                    Err(modal) => self.open_modal(modal),
-                    _ => {}
-                }
+                self.request_close(CloseAction::OpenSet);
+            }
+            if MenuItem::new(im_str!("Save background set")).shortcut(im_str!("Ctrl+S")).enabled(self.set.is_some()).build(ui) {
+                self.save_set();
             }
             if MenuItem::new(im_str!("Save background set as...")).enabled(self.set.is_some()).build(ui) {
-                // TODO: Use the last path our set was saved as, if available
-                match utils::nfd_handler(nfd::open_save_dialog(Some("bgs"), None), "save location") { 
-                    Ok(Some(path)) => match self.set.as_ref().unwrap().save(path) {
-                        Ok(_) => { /* TODO: Indicate success somehow? */ },
-                        Err(e) => self.open_modal(ErrorModal::new("An error occured while saving the background set.", Some(e)))
-                    }
-                    Err(modal) => self.open_modal(modal),
-                    _ => {}
-                }
+                self.save_set_as();
+            }
+            ui.separator();
+            if MenuItem::new(im_str!("Exit")).build(ui) {
+                self.request_close(CloseAction::Exit);
             }
         });
         ui.menu(im_str!("Set"), self.set.is_some(), || {
             if MenuItem::new(im_str!("Edit set information...")).build(ui) {
                 self.open_modal(ChangeSetInfo::new(self.set.as_ref().unwrap()))
             }
-            let set = self.set.as_mut().unwrap();
+            if MenuItem::new(im_str!("Check set...")).build(ui) {
+                self.open_modal(CheckSet::new(self.set.as_ref().unwrap().to_saved()))
+            }
+            if MenuItem::new(im_str!("Merge another copy...")).build(ui) {
+                self.open_modal(MergeSets::new())
+            }
+            let set = self.set.as_ref().unwrap();
             if MenuItem::new(im_str!("Rebuild image folder")).enabled(set.image_folder().is_some()).build(ui) {
-                match set.rebuild_image_folder() {
-                    Ok(skipped) => self.open_modal(RebuildSuccess::new(skipped)),
-                    Err(e) => self.open_modal(ErrorModal::new("An error occured while rebuilding the image folder.", Some(e))),
-                }
+                self.rebuild_image_folder(false);
             }
         });
         ui.menu(im_str!("View"), true, || {
@@ -20,7 +20,7 @@ impl GuiState {
     
     fn draw_image<T: Textures + ?Sized>(&mut self, frame: Frame<T>, background: usize) {
         let Frame { ui, textures, resources } = frame;
-        let ActiveSet { set, image_cache } = self.set.as_mut().expect("Cannot edit when no background set is open!");
+        let ActiveSet { set, image_cache, dirty, .. } = self.set.as_mut().expect("Cannot edit when no background set is open!");
         let background = &mut set.backgrounds[background];
         let original = set.sources[background.source].original(&background.original);
         if let Some(original) = original.as_option() {
@@ -36,7 +36,7 @@ impl GuiState {
         let offset = (avail - size) / 2.0;
         ui.move_cursor(offset.into());
         match background.edit_crop_region(vec2![set.resolution.0 as f32, set.resolution.1 as f32]) {
-            Ok(crop_region) => CroppableImage::new(texture, size).build(ui, crop_region),
+            Ok(crop_region) => *dirty |= CroppableImage::new(texture, size).build(ui, crop_region),
             Err(_) => unimplemented!()
         }
         ui.move_cursor([0.0, offset.y]);
@@ -53,10 +53,12 @@ impl GuiState {
             None => format!("{} - (original unavailable)", background.name),
         };
         ui.text(header);
-        if ui.input_text(im_str!("Name"), &mut buf).flags(ImGuiInputTextFlags::CallbackResize).build() {
+        let renamed = ui.input_text(im_str!("Name"), &mut buf).flags(ImGuiInputTextFlags::CallbackResize).build();
+        if renamed {
             background.name = buf.to_str().to_owned();
         }
         ui.input_text(im_str!("Location"), &mut ImString::new(&background.location)).read_only(true).build();
+        set.dirty |= renamed;
         if ui.button(im_str!("Preview"), AUTO_SIZE) {
 
         }
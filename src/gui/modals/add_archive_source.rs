@@ -0,0 +1,48 @@
+use std::path::PathBuf;
+
+use super::ModalInterface;
+use crate::gui::prelude::*;
+use crate::sources::{ArchiveSource, ArchiveFormat};
+use crate::utils::OptionExt;
+
+pub struct AddArchiveSource { archive: Option<PathBuf>, name_buf: ImString }
+impl ModalInterface for AddArchiveSource {
+    fn id(&self) -> &str { "addarchivesource" }
+    fn title(&self) -> &str { "Add source from archive..." }
+    fn display<T: Textures + ?Sized>(mut self, state: &mut GuiState, frame: Frame<T>) {
+        let Frame { ui, .. } = frame;
+        
+        let display_archive = self.archive.deref().map(|f| f.to_string_lossy()).unwrap_or("(none)".into());
+        ui.input_text(im_str!("Source archive"), &mut ImString::new(display_archive)).read_only(true).build();
+        ui.same_line(0.0);
+        if ui.button(im_str!("Choose..."), AUTO_SIZE) {
+            match utils::nfd_handler(nfd::open_file_dialog(Some("zip,tar,gz,tgz"), None), "source archive") {
+                Ok(Some(path)) => self.archive = Some(path),
+                Err(modal) => { state.open_modal(modal); return }
+                _ => {},
+            }
+        }
+
+        ui.input_text(im_str!("Source name"), &mut self.name_buf).flags(imgui::ImGuiInputTextFlags::CallbackResize).build();
+
+        let format = self.archive.deref().and_then(ArchiveFormat::detect);
+        if self.archive.is_some() && format.is_none() {
+            ui.text("Only .zip, .tar, .tar.gz and .tgz archives are supported.");
+        }
+
+        let is_ok = format.is_some() && self.name_buf.to_str().trim().len() > 0;
+        if ui.button_hack(im_str!("OK"), AUTO_SIZE, is_ok) {
+            state.add_source(ArchiveSource::new(self.archive.unwrap(), self.name_buf.to_str(), format.unwrap()));
+            return
+        }
+        ui.same_line(0.0);
+        if ui.button(im_str!("Cancel"), AUTO_SIZE) { return }
+        state.open_modal(self)
+    }
+}
+
+impl AddArchiveSource {
+    pub fn new() -> AddArchiveSource {
+        AddArchiveSource { archive: None, name_buf: ImString::new("") }
+    }
+}
\ No newline at end of file
@@ -0,0 +1,32 @@
+use super::ModalInterface;
+use crate::gui::prelude::*;
+use crate::sources::CommandSource;
+
+pub struct AddCommandSource { command_buf: ImString, name_buf: ImString }
+impl ModalInterface for AddCommandSource {
+    fn id(&self) -> &str { "addcommandsource" }
+    fn title(&self) -> &str { "Add source from command..." }
+    fn display<T: Textures + ?Sized>(mut self, state: &mut GuiState, frame: Frame<T>) {
+        let Frame { ui, .. } = frame;
+
+        ui.input_text(im_str!("Command"), &mut self.command_buf).flags(imgui::ImGuiInputTextFlags::CallbackResize).build();
+        ui.text_disabled("The command should print a JSON list of objects with id, path, name and location fields.");
+
+        ui.input_text(im_str!("Source name"), &mut self.name_buf).flags(imgui::ImGuiInputTextFlags::CallbackResize).build();
+
+        let is_ok = self.command_buf.to_str().trim().len() > 0 && self.name_buf.to_str().trim().len() > 0;
+        if ui.button_hack(im_str!("OK"), AUTO_SIZE, is_ok) {
+            state.add_source(CommandSource::new(self.command_buf.to_str().trim(), self.name_buf.to_str()));
+            return
+        }
+        ui.same_line(0.0);
+        if ui.button(im_str!("Cancel"), AUTO_SIZE) { return }
+        state.open_modal(self)
+    }
+}
+
+impl AddCommandSource {
+    pub fn new() -> AddCommandSource {
+        AddCommandSource { command_buf: ImString::new(""), name_buf: ImString::new("") }
+    }
+}
\ No newline at end of file
@@ -2,16 +2,24 @@ use std::path::PathBuf;
 
 use super::ModalInterface;
 use crate::gui::prelude::*;
-use crate::sources::FolderSource;
+use crate::sources::{FolderSource, ScanOptions};
 use crate::utils::OptionExt;
 
-pub struct AddFolderSource { folder: Option<PathBuf>, name_buf: ImString }
+pub struct AddFolderSource {
+    folder: Option<PathBuf>,
+    name_buf: ImString,
+    recursive: bool,
+    max_depth: i32,
+    include_buf: ImString,
+    exclude_buf: ImString,
+}
+
 impl ModalInterface for AddFolderSource {
     fn id(&self) -> &str { "addfoldersource" }
     fn title(&self) -> &str { "Add source from folder..." }
     fn display<T: Textures + ?Sized>(mut self, state: &mut GuiState, frame: Frame<T>) {
         let Frame { ui, .. } = frame;
-        
+
         let display_folder = self.folder.deref().map(|f| f.to_string_lossy()).unwrap_or("(none)".into());
         ui.input_text(im_str!("Source folder"), &mut ImString::new(display_folder)).read_only(true).build();
         ui.same_line(0.0);
@@ -25,9 +33,24 @@ impl ModalInterface for AddFolderSource {
 
         ui.input_text(im_str!("Source name"), &mut self.name_buf).flags(imgui::ImGuiInputTextFlags::CallbackResize).build();
 
-        let is_ok = self.folder.is_some() && self.name_buf.to_str().trim().len() > 0;
+        ui.checkbox(im_str!("Include subfolders"), &mut self.recursive);
+        if self.recursive {
+            ui.input_int(im_str!("Maximum depth (0 for no limit)"), &mut self.max_depth).build();
+            self.max_depth = i32::max(0, self.max_depth);
+        }
+        ui.input_text(im_str!("Include patterns"), &mut self.include_buf).flags(imgui::ImGuiInputTextFlags::CallbackResize).build();
+        ui.input_text(im_str!("Exclude patterns"), &mut self.exclude_buf).flags(imgui::ImGuiInputTextFlags::CallbackResize).build();
+        ui.text_disabled("Patterns are separated by semicolons and matched against paths within the folder, e.g. **/*.png");
+
+        let scan = self.scan_options();
+        let patterns_ok = match scan.validate() {
+            Ok(_) => true,
+            Err(e) => { ui.text(format!("Invalid pattern: {}", e.msg)); false }
+        };
+
+        let is_ok = self.folder.is_some() && self.name_buf.to_str().trim().len() > 0 && patterns_ok;
         if ui.button_hack(im_str!("OK"), AUTO_SIZE, is_ok) {
-            state.add_source(FolderSource::new(self.folder.unwrap(), self.name_buf.to_str()));
+            state.add_source(FolderSource::new(self.folder.unwrap(), self.name_buf.to_str(), scan));
             return
         }
         ui.same_line(0.0);
@@ -38,6 +61,23 @@ impl ModalInterface for AddFolderSource {
 
 impl AddFolderSource {
     pub fn new() -> AddFolderSource {
-        AddFolderSource { folder: None, name_buf: ImString::new("") }
+        AddFolderSource {
+            folder: None,
+            name_buf: ImString::new(""),
+            recursive: false,
+            max_depth: 0,
+            include_buf: ImString::new(""),
+            exclude_buf: ImString::new(""),
+        }
+    }
+
+    fn scan_options(&self) -> ScanOptions {
+        let split = |buf: &ImString| buf.to_str().split(';').map(str::trim).filter(|p| !p.is_empty()).map(str::to_owned).collect();
+        ScanOptions {
+            recursive: self.recursive,
+            max_depth: if self.max_depth > 0 { Some(self.max_depth as usize) } else { None },
+            include: split(&self.include_buf),
+            exclude: split(&self.exclude_buf),
+        }
     }
 }
\ No newline at end of file
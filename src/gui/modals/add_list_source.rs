@@ -0,0 +1,44 @@
+use std::path::PathBuf;
+
+use super::ModalInterface;
+use crate::gui::prelude::*;
+use crate::sources::ListSource;
+use crate::utils::OptionExt;
+
+pub struct AddListSource { list: Option<PathBuf>, name_buf: ImString }
+impl ModalInterface for AddListSource {
+    fn id(&self) -> &str { "addlistsource" }
+    fn title(&self) -> &str { "Add source from file list..." }
+    fn display<T: Textures + ?Sized>(mut self, state: &mut GuiState, frame: Frame<T>) {
+        let Frame { ui, .. } = frame;
+        
+        let display_list = self.list.deref().map(|f| f.to_string_lossy()).unwrap_or("(none)".into());
+        ui.input_text(im_str!("File list"), &mut ImString::new(display_list)).read_only(true).build();
+        ui.same_line(0.0);
+        if ui.button(im_str!("Choose..."), AUTO_SIZE) {
+            match utils::nfd_handler(nfd::open_file_dialog(Some("txt,m3u,m3u8"), None), "file list") {
+                Ok(Some(path)) => self.list = Some(path),
+                Err(modal) => { state.open_modal(modal); return }
+                _ => {},
+            }
+        }
+        ui.text_disabled("The list should contain one image path per line. Lines starting with # are ignored.");
+
+        ui.input_text(im_str!("Source name"), &mut self.name_buf).flags(imgui::ImGuiInputTextFlags::CallbackResize).build();
+
+        let is_ok = self.list.is_some() && self.name_buf.to_str().trim().len() > 0;
+        if ui.button_hack(im_str!("OK"), AUTO_SIZE, is_ok) {
+            state.add_source(ListSource::new(self.list.unwrap(), self.name_buf.to_str()));
+            return
+        }
+        ui.same_line(0.0);
+        if ui.button(im_str!("Cancel"), AUTO_SIZE) { return }
+        state.open_modal(self)
+    }
+}
+
+impl AddListSource {
+    pub fn new() -> AddListSource {
+        AddListSource { list: None, name_buf: ImString::new("") }
+    }
+}
\ No newline at end of file
@@ -0,0 +1,25 @@
+use super::ModalInterface;
+use crate::gui::prelude::*;
+
+/// Asks before rebuilding into an image folder which already has files in it that dbgm didn't write.
+pub struct AdoptImageFolder;
+
+impl ModalInterface for AdoptImageFolder {
+    fn id(&self) -> &str { "adoptimagefolder" }
+    fn title(&self) -> &str { "Image folder not empty" }
+    fn display<T: Textures + ?Sized>(self, state: &mut GuiState, frame: Frame<T>) {
+        let Frame { ui, .. } = frame;
+        let set = state.set.as_ref().expect("Cannot rebuild the image folder when no background set is open!");
+        let folder = set.image_folder().expect("Cannot rebuild the image folder when none is set!");
+        ui.text(im_str!("The image folder {} already has files in it which weren't created by dbgm.", folder.to_string_lossy()));
+        ui.text("They will be kept, but your desktop will show them alongside the set's backgrounds.");
+
+        if ui.button(im_str!("Use this folder"), AUTO_SIZE) {
+            state.rebuild_image_folder(true);
+            return
+        }
+        ui.same_line(0.0);
+        if ui.button(im_str!("Cancel"), AUTO_SIZE) { return }
+        state.open_modal(self)
+    }
+}
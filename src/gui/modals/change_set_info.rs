@@ -3,10 +3,19 @@ use std::path::{Path, PathBuf};
 use super::ModalInterface;
 use crate::gui::prelude::*;
 
-use crate::background::BackgroundSet;
+use crate::background::{BackgroundSet, ResizeFilter, OutputSettings, OutputFormat, PngCompression};
 use crate::utils::OptionExt;
 
-pub struct ChangeSetInfo { image_folder: Option<PathBuf>, name_buf: ImString }
+pub struct ChangeSetInfo {
+    image_folder: Option<PathBuf>,
+    name_buf: ImString,
+    backup_count: i32,
+    relative_paths: bool,
+    resize_filter: ResizeFilter,
+    output_settings: OutputSettings,
+    quality: i32,
+}
+
 impl ModalInterface for ChangeSetInfo {
     fn id(&self) -> &str { "changesetinfo" }
     fn title(&self) -> &str { "Background set information" }
@@ -28,9 +37,59 @@ impl ModalInterface for ChangeSetInfo {
         }
         ui.new_line();
 
+        ui.input_int(im_str!("Backups to keep"), &mut self.backup_count).build();
+        self.backup_count = i32::max(0, self.backup_count);
+        if ui.is_item_hovered() {
+            ui.tooltip_text("0 turns backups off. Backups which already exist are left alone.");
+        }
+        ui.checkbox(im_str!("Save paths relative to the set file"), &mut self.relative_paths);
+        if ui.is_item_hovered() {
+            ui.tooltip_text("Lets the set be opened from another machine if its folder, sources and all, is copied or synced there.");
+        }
+        ui.new_line();
+
+        ui.text("Resize filter");
+        for &filter in ResizeFilter::ALL.iter() {
+            ui.same_line(0.0);
+            if ui.radio_button_bool(&im_str!("{}", filter.name()), self.resize_filter == filter) { self.resize_filter = filter; }
+        }
+        ui.text_disabled("Used to scale backgrounds to the set's resolution. Lanczos3 is the sharpest; Nearest is the fastest.");
+        ui.new_line();
+
+        let output = &mut self.output_settings;
+        ui.text("Output format");
+        for &format in OutputFormat::ALL.iter() {
+            ui.same_line(0.0);
+            if ui.radio_button_bool(&im_str!("{}", format.name()), output.format == format) { output.format = format; }
+        }
+        match output.format {
+            OutputFormat::Png => {
+                ui.text("Compression");
+                for &compression in PngCompression::ALL.iter() {
+                    ui.same_line(0.0);
+                    let label = im_str!("{}###Compression{}", compression.name(), compression.name());
+                    if ui.radio_button_bool(&label, output.png_compression == compression) { output.png_compression = compression; }
+                }
+                ui.checkbox(im_str!("Optimise losslessly"), &mut output.optimize);
+                if ui.is_item_hovered() {
+                    ui.tooltip_text("Makes the images smaller without changing them, but the image folder takes much longer to rebuild.");
+                }
+            },
+            OutputFormat::Jpeg | OutputFormat::WebP => {
+                ui.input_int(im_str!("Quality"), &mut self.quality).build();
+                self.quality = i32::max(1, i32::min(100, self.quality));
+            },
+        }
+        ui.new_line();
+
         if ui.button(im_str!("OK"), AUTO_SIZE) {
+            set.set_backup_count(self.backup_count as usize);
+            set.set_relative_paths(self.relative_paths);
+            set.set_resize_filter(self.resize_filter);
+            set.set_output_settings(OutputSettings { quality: self.quality as u8, ..self.output_settings });
             if let Some(folder) = self.image_folder { set.set_image_folder(folder); }
             if self.name_buf.to_str().trim() != "" { set.set_name(self.name_buf.to_str().to_string()); }
+            set.dirty = true;
             return
         }
         ui.same_line(0.0);
@@ -44,6 +103,11 @@ impl ChangeSetInfo {
         ChangeSetInfo { 
             image_folder: set.image_folder().map(Path::to_owned), 
             name_buf: ImString::new(set.name().clone().unwrap_or("")),
+            backup_count: set.backup_count() as i32,
+            relative_paths: set.relative_paths(),
+            resize_filter: set.resize_filter(),
+            output_settings: set.output_settings().clone(),
+            quality: set.output_settings().quality as i32,
         }
     }
 }
\ No newline at end of file
@@ -0,0 +1,75 @@
+use super::{ModalInterface, ErrorModal};
+use crate::gui::prelude::*;
+use crate::background::{SavedBackgroundSet, Repair};
+
+struct ProblemEntry {
+    description: String,
+    repairs: Vec<(Repair, String, bool)>,
+}
+
+/// Lists the problems found in the open set, and makes whichever of the offered repairs the user picks.
+pub struct CheckSet {
+    saved: SavedBackgroundSet,
+    problems: Vec<ProblemEntry>,
+}
+
+impl ModalInterface for CheckSet {
+    fn id(&self) -> &str { "checkset" }
+    fn title(&self) -> &str { "Check background set" }
+    fn display<T: Textures + ?Sized>(mut self, state: &mut GuiState, frame: Frame<T>) {
+        let Frame { ui, .. } = frame;
+        if self.problems.is_empty() {
+            ui.text("No problems were found in this background set.");
+            if ui.button(im_str!("OK"), AUTO_SIZE) { return }
+            state.open_modal(self);
+            return
+        }
+
+        ui.text(im_str!("{} problems were found in this background set.", self.problems.len()));
+        ui.text("Removing sources or backgrounds can't be undone except by not saving the set afterwards.");
+        let size = [ui.current_font_size() * 40.0, ui.io().display_size[1] / 2.0];
+        ChildWindow::new(im_str!("Problems")).size(size).border(true).build(ui, || {
+            for (i, problem) in self.problems.iter_mut().enumerate() {
+                ui.text_wrapped(&im_str!("{}", problem.description));
+                if problem.repairs.is_empty() {
+                    ui.text_disabled("This can't be repaired automatically.");
+                }
+                for (j, (_, description, selected)) in problem.repairs.iter_mut().enumerate() {
+                    ui.checkbox(&im_str!("{}###Repair{}.{}", description, i, j), selected);
+                }
+                ui.separator();
+            }
+        });
+
+        if ui.button(im_str!("Repair"), AUTO_SIZE) {
+            let repairs = self.problems.iter().flat_map(|p| p.repairs.iter())
+                .filter(|(_, _, selected)| *selected).map(|(repair, _, _)| *repair).collect::<Vec<_>>();
+            if repairs.is_empty() { return }
+            if let Err(e) = self.saved.repair(&repairs) {
+                state.open_modal(ErrorModal::new("The background set could not be repaired.", Some(e)));
+                return
+            }
+            let old_set = state.set.as_mut().expect("Cannot check a background set when none is open!");
+            let (path, lock) = (old_set.path.take(), old_set.lock.take());
+            let (mut set, _) = self.saved.load();
+            set.lock = lock;
+            state.open_background_set(set, path);
+            state.set.as_mut().unwrap().dirty = true;
+            return
+        }
+        ui.same_line(0.0);
+        if ui.button(im_str!("Cancel"), AUTO_SIZE) { return }
+        state.open_modal(self)
+    }
+}
+
+impl CheckSet {
+    pub fn new(saved: SavedBackgroundSet) -> CheckSet {
+        let problems = saved.check().iter().map(|problem| ProblemEntry {
+            description: problem.describe(&saved),
+            // Only the safe repairs are chosen for the user, so nothing is removed unless they ask for it
+            repairs: problem.repairs().into_iter().map(|r| (r, r.describe(&saved), r.is_safe())).collect(),
+        }).collect();
+        CheckSet { saved, problems }
+    }
+}
@@ -21,6 +21,10 @@ const DELETED_DESCRIPTION: &'static str = "\
     If you choose Don't Remove, these backgrounds will remain in the library, but will be marked as \
     missing an original, and will not be able to be edited.";
 
+const MOVED_DESCRIPTION: &'static str = "\
+    The original associated with the following backgrounds has been moved or renamed, but its content is \
+    unchanged. The backgrounds will keep all of their edits.";
+
 const UNAVAILABLE_DESCRIPTION: &'static str = "\
     The original associated with the following backgrounds cannot be accessed. This condition may be temporary\
     or permanent. You will not be able to edit any of the backgrounds until the original becomes available again.";
@@ -59,6 +63,7 @@ impl ModalInterface for ConfirmChanges {
                 ChangeKind::Altered => self.display_altered(set, frame2, &change.key),
                 ChangeKind::Deleted => self.display_deleted(set, frame2, &change.key),
                 ChangeKind::Unavailable(cause) => self.display_unavailable(set, frame2, &change.key, &cause),
+                ChangeKind::Moved { from } => self.display_moved(set, frame2, from),
             };
 
             match result {
@@ -122,6 +127,14 @@ impl ConfirmChanges {
                     background.flags.insert(DesktopBackgroundFlags::ORIGINAL_MISSING);
                 }
             },
+            (ChangeKind::Moved { from }, _) => {
+                let original = set.sources[self.source].original(&key);
+                let original = if let OriginalResult::Original(o) = original { o } else { panic!("Got an invalid key from reload!"); };
+                let source = self.source;
+                for background in set.backgrounds.values_mut().filter(|b| b.source == source && b.original.compare(&from) != KeyRelation::Distinct) {
+                    background.repoint(key.clone(), original);
+                }
+            },
             (ChangeKind::Unavailable(_), _) => { 
                 for background in set.backgrounds.values_mut().filter(|b| b.original.compare(&key) == KeyRelation::SameOriginal) {
                     background.mark_unavailable();
@@ -195,6 +208,18 @@ impl ConfirmChanges {
         None
     }
 
+    fn display_moved<T: Textures + ?Sized>(&mut self, set: &mut ActiveSet, frame: Frame<T>, from: &OriginalKey) -> Option<ChangeResult> {
+        let ui = frame.ui;
+
+        ui.text_wrapped(&im_str!("{}", MOVED_DESCRIPTION));
+        ui.spacing();
+        ConfirmChanges::display_affected(set, reborrow_frame!(frame), from);
+        ui.spacing();
+
+        if ui.button(im_str!("OK"), AUTO_SIZE) { return Some(ChangeResult::Accept); }
+        None
+    }
+
     fn display_new<T: Textures + ?Sized>(&mut self, set: &mut ActiveSet, frame: Frame<T>, key: &OriginalKey) -> Option<ChangeResult> {
         let Frame { ui, resources, textures } = frame;
         
@@ -232,11 +257,13 @@ pub struct ResultCache {
     altered: Option<(ChangeResult, bool)>,
     deleted: Option<(ChangeResult, bool)>,
     unavailable: Option<(ChangeResult, bool)>,
+    moved: Option<(ChangeResult, bool)>,
 }
 
 impl ResultCache {
     pub fn new() -> ResultCache {
-        ResultCache { new: None, altered: None, deleted: None, unavailable: None }
+        // Moves never lose anything, so there's no need to ask about them.
+        ResultCache { new: None, altered: None, deleted: None, unavailable: None, moved: Some((ChangeResult::Accept, false)) }
     }
 
     fn select<E: std::fmt::Debug>(&mut self, kind: &ChangeKind<E>) -> &mut Option<(ChangeResult, bool)> {
@@ -245,6 +272,7 @@ impl ResultCache {
             ChangeKind::Altered => &mut self.altered,
             ChangeKind::Deleted => &mut self.deleted,
             ChangeKind::Unavailable(_) => &mut self.unavailable,
+            ChangeKind::Moved { .. } => &mut self.moved,
         }
     }
 
@@ -0,0 +1,113 @@
+use std::path::{Path, PathBuf};
+
+use super::{ModalInterface, ErrorModal};
+use crate::gui::prelude::*;
+use crate::background::{BackgroundSet, SavedBackgroundSet, Merge, Side, OpenMode, Error};
+use crate::utils::OptionExt;
+
+/// Merges another copy of the open set into it, given the copy both were made from. Asks the user to settle any
+/// conflicts before the result replaces the open set.
+pub struct MergeSets {
+    theirs: Option<PathBuf>,
+    base: Option<PathBuf>,
+    merge: Option<(Merge, Vec<Side>)>,
+}
+
+impl ModalInterface for MergeSets {
+    fn id(&self) -> &str { "mergesets" }
+    fn title(&self) -> &str { "Merge another copy" }
+    fn display<T: Textures + ?Sized>(mut self, state: &mut GuiState, frame: Frame<T>) {
+        let Frame { ui, .. } = frame;
+        if self.merge.is_none() { return self.choose_files(state, ui) }
+        let (merge, sides) = self.merge.as_mut().unwrap();
+
+        ui.text(im_str!("{} conflicts need to be settled. Choose which copy's version to keep for each.", merge.conflicts.len()));
+        let size = [ui.current_font_size() * 40.0, ui.io().display_size[1] / 2.0];
+        ChildWindow::new(im_str!("Conflicts")).size(size).border(true).build(ui, || {
+            for (i, (conflict, side)) in merge.conflicts.iter().zip(sides.iter_mut()).enumerate() {
+                ui.text_wrapped(&im_str!("{}", merge.describe(conflict)));
+                if ui.radio_button_bool(&im_str!("Ours###Ours{}", i), *side == Side::Ours) { *side = Side::Ours; }
+                ui.same_line(0.0);
+                if ui.radio_button_bool(&im_str!("Theirs###Theirs{}", i), *side == Side::Theirs) { *side = Side::Theirs; }
+                ui.separator();
+            }
+        });
+
+        if ui.button(im_str!("Merge"), AUTO_SIZE) {
+            let (merge, sides) = self.merge.unwrap();
+            apply_merge(state, merge.resolve(&sides));
+            return
+        }
+        ui.same_line(0.0);
+        if ui.button(im_str!("Cancel"), AUTO_SIZE) { return }
+        state.open_modal(self)
+    }
+}
+
+impl MergeSets {
+    pub fn new() -> MergeSets {
+        MergeSets { theirs: None, base: None, merge: None }
+    }
+
+    fn choose_files(mut self, state: &mut GuiState, ui: &Ui) {
+        let display_theirs = self.theirs.deref().map(|f| f.to_string_lossy()).unwrap_or("(none)".into());
+        ui.input_text(im_str!("Their copy"), &mut ImString::new(display_theirs)).read_only(true).build();
+        ui.same_line(0.0);
+        if ui.button(im_str!("Choose...###ChooseTheirs"), AUTO_SIZE) {
+            match utils::nfd_handler(nfd::open_file_dialog(Some("bgs;bgsb"), None), "background set") {
+                Ok(Some(path)) => self.theirs = Some(path),
+                Err(modal) => { state.open_modal(modal); return }
+                _ => {},
+            }
+        }
+
+        let display_base = self.base.deref().map(|f| f.to_string_lossy()).unwrap_or("(none)".into());
+        ui.input_text(im_str!("Common ancestor"), &mut ImString::new(display_base)).read_only(true).build();
+        ui.same_line(0.0);
+        if ui.button(im_str!("Choose...###ChooseBase"), AUTO_SIZE) {
+            match utils::nfd_handler(nfd::open_file_dialog(Some("bgs;bgsb;bak"), None), "background set") {
+                Ok(Some(path)) => self.base = Some(path),
+                Err(modal) => { state.open_modal(modal); return }
+                _ => {},
+            }
+        }
+        ui.text_disabled("The common ancestor is the set as it was before the copies were edited separately, e.g. a backup.");
+
+        let is_ok = self.theirs.is_some() && self.base.is_some();
+        if ui.button_hack(im_str!("Merge"), AUTO_SIZE, is_ok) {
+            let set = state.set.as_ref().expect("Cannot merge when no background set is open!");
+            match (read_saved(self.base.as_ref().unwrap()), read_saved(self.theirs.as_ref().unwrap())) {
+                (Ok(base), Ok(theirs)) => {
+                    let merge = Merge::new(&base, &set.to_saved(), theirs);
+                    if merge.conflicts.is_empty() {
+                        apply_merge(state, merge.resolve(&[]));
+                        return
+                    }
+                    let sides = vec![Side::Ours; merge.conflicts.len()];
+                    self.merge = Some((merge, sides));
+                },
+                (Err(e), _) | (_, Err(e)) => {
+                    state.open_modal(ErrorModal::new("A background set could not be loaded for merging.", Some(e)));
+                    return
+                }
+            }
+        }
+        ui.same_line(0.0);
+        if ui.button(im_str!("Cancel"), AUTO_SIZE) { return }
+        state.open_modal(self)
+    }
+}
+
+/// Read a set file for merging. It's loaded in full so that any relative paths in it are resolved.
+fn read_saved(path: &Path) -> Result<SavedBackgroundSet, Error> {
+    BackgroundSet::load(path, OpenMode::ReadOnly).map(|(set, _)| set.to_saved())
+}
+
+fn apply_merge(state: &mut GuiState, merged: SavedBackgroundSet) {
+    let old_set = state.set.as_mut().expect("Cannot merge when no background set is open!");
+    let (path, lock) = (old_set.path.take(), old_set.lock.take());
+    let (mut set, _) = merged.load();
+    set.lock = lock;
+    state.open_background_set(set, path);
+    state.set.as_mut().unwrap().dirty = true;
+}
@@ -5,16 +5,42 @@ use crate::gui::prelude::*;
 pub mod error;
 pub mod change_set_info;
 pub mod add_folder_source;
+pub mod add_archive_source;
+pub mod add_list_source;
+pub mod add_command_source;
 pub mod confirm_changes;
 pub mod remove_source;
+pub mod relocate_source;
 pub mod rebuild_success;
+pub mod restore_backup;
+pub mod recover_set;
+pub mod unsaved_changes;
+pub mod check_set;
+pub mod set_locked;
+pub mod merge_sets;
+pub mod adopt_image_folder;
+pub mod rebuild_progress;
+pub mod preparing_reload;
 
 pub use error::ErrorModal;
 pub use change_set_info::ChangeSetInfo;
 pub use add_folder_source::AddFolderSource;
+pub use add_archive_source::AddArchiveSource;
+pub use add_list_source::AddListSource;
+pub use add_command_source::AddCommandSource;
 pub use confirm_changes::ConfirmChanges;
 pub use remove_source::RemoveSource;
+pub use relocate_source::RelocateSource;
 pub use rebuild_success::RebuildSuccess;
+pub use restore_backup::RestoreBackup;
+pub use recover_set::RecoverSet;
+pub use unsaved_changes::UnsavedChanges;
+pub use check_set::CheckSet;
+pub use set_locked::SetLocked;
+pub use merge_sets::MergeSets;
+pub use adopt_image_folder::AdoptImageFolder;
+pub use rebuild_progress::RebuildProgress;
+pub use preparing_reload::PreparingReload;
 
 #[enum_dispatch]
 pub trait ModalInterface {
@@ -31,9 +57,22 @@ pub enum Modal {
     ErrorModal,
     ChangeSetInfo,
     AddFolderSource,
+    AddArchiveSource,
+    AddListSource,
+    AddCommandSource,
     ConfirmChanges,
     RemoveSource,
+    RelocateSource,
     RebuildSuccess,
+    RestoreBackup,
+    RecoverSet,
+    UnsavedChanges,
+    CheckSet,
+    SetLocked,
+    MergeSets,
+    AdoptImageFolder,
+    RebuildProgress,
+    PreparingReload,
 }
 
 impl GuiState {
@@ -0,0 +1,47 @@
+use std::any::Any;
+use std::sync::mpsc::{self, Receiver, TryRecvError};
+use std::thread;
+
+use super::{ModalInterface, confirm_changes::ResultCache};
+use crate::gui::prelude::*;
+use crate::sources::ReloadPreparation;
+
+/// Shown while a source does the slow part of a reload on another thread, e.g. running a command.
+pub struct PreparingReload {
+    source: usize,
+    deep: bool,
+    result_cache: ResultCache,
+    prepared: Receiver<Box<dyn Any + Send>>,
+}
+
+impl ModalInterface for PreparingReload {
+    fn id(&self) -> &str { "preparingreload" }
+    fn title(&self) -> &str { "Reloading source" }
+    fn display<T: Textures + ?Sized>(self, state: &mut GuiState, frame: Frame<T>) {
+        let Frame { ui, .. } = frame;
+        let set = state.set.as_mut().expect("Cannot reload a source when no background set is open!");
+        match self.prepared.try_recv() {
+            Ok(prepared) => {
+                set.sources[self.source].accept_prepared(prepared);
+                state.finish_reload(self.source, self.deep, self.result_cache);
+                return
+            },
+            // The preparation panicked, so just let the reload do everything itself.
+            Err(TryRecvError::Disconnected) => { state.finish_reload(self.source, self.deep, self.result_cache); return },
+            Err(TryRecvError::Empty) => {},
+        }
+
+        ui.text(im_str!("Reloading {}...", set.sources[self.source].name()));
+        // Whatever is running carries on, but its result is ignored.
+        if ui.button(im_str!("Cancel"), AUTO_SIZE) { return }
+        state.open_modal(self)
+    }
+}
+
+impl PreparingReload {
+    pub fn new(source: usize, deep: bool, result_cache: ResultCache, preparation: ReloadPreparation) -> PreparingReload {
+        let (sender, prepared) = mpsc::channel();
+        thread::spawn(move || { let _ = sender.send(preparation()); });
+        PreparingReload { source, deep, result_cache, prepared }
+    }
+}
@@ -0,0 +1,45 @@
+use super::{ModalInterface, ErrorModal, RebuildSuccess};
+use crate::gui::prelude::*;
+use crate::background::{Rebuild, RebuildError};
+
+/// Shown while the image folder is rebuilt in the background, so that it can be cancelled.
+pub struct RebuildProgress {
+    rebuild: Rebuild,
+}
+
+impl ModalInterface for RebuildProgress {
+    fn id(&self) -> &str { "rebuildprogress" }
+    fn title(&self) -> &str { "Rebuilding image folder" }
+    fn display<T: Textures + ?Sized>(self, state: &mut GuiState, frame: Frame<T>) {
+        let Frame { ui, .. } = frame;
+        let set = state.set.as_mut().expect("Cannot rebuild the image folder when no background set is open!");
+        match self.rebuild.poll(set) {
+            Some(Ok(skipped)) => { state.open_modal(RebuildSuccess::new(skipped)); return },
+            Some(Err(RebuildError::Cancelled)) => return,
+            Some(Err(e)) => {
+                state.open_modal(ErrorModal::new("An error occured while rebuilding the image folder.", Some(e)));
+                return
+            },
+            None => {},
+        }
+
+        let (done, total) = self.rebuild.progress();
+        ui.text(im_str!("{} of {} changed backgrounds done.", done, total));
+        let fraction = if total == 0 { 1.0 } else { done as f32 / total as f32 };
+        ui.progress_bar(fraction).size([ui.current_font_size() * 25.0, 0.0]).build();
+        ui.text_disabled(self.rebuild.current().unwrap_or_default());
+
+        if self.rebuild.is_cancelled() {
+            ui.text("Cancelling. The image folder will be left as it was.");
+        } else if ui.button(im_str!("Cancel"), AUTO_SIZE) {
+            self.rebuild.cancel();
+        }
+        state.open_modal(self)
+    }
+}
+
+impl RebuildProgress {
+    pub fn new(rebuild: Rebuild) -> RebuildProgress {
+        RebuildProgress { rebuild }
+    }
+}
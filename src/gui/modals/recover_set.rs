@@ -0,0 +1,56 @@
+use chrono::{DateTime, Local};
+
+use super::{ModalInterface, ErrorModal};
+use crate::gui::prelude::*;
+use crate::background::{Recovery, SetLock};
+
+/// Offered at startup when a recovery file holds work that was never saved.
+pub struct RecoverSet {
+    recovery: Recovery,
+}
+
+impl ModalInterface for RecoverSet {
+    fn id(&self) -> &str { "recoverset" }
+    fn title(&self) -> &str { "Recover unsaved changes" }
+    fn display<T: Textures + ?Sized>(self, state: &mut GuiState, frame: Frame<T>) {
+        let Frame { ui, .. } = frame;
+        let time = DateTime::<Local>::from(self.recovery.time).format("%Y-%m-%d %H:%M:%S");
+        match &self.recovery.set_path {
+            Some(path) => ui.text(im_str!("Unsaved changes to the background set at {} were autosaved at {}.", path.to_string_lossy(), time)),
+            None => ui.text(im_str!("Changes to a background set that was never saved were autosaved at {}.", time)),
+        }
+        ui.text("The program may have closed unexpectedly. Would you like to recover them?");
+
+        if ui.button(im_str!("Recover"), AUTO_SIZE) {
+            let path = self.recovery.set_path.clone();
+            let file = match self.recovery.claim() {
+                Ok(file) => file,
+                Err(e) => return state.open_modal(ErrorModal::new("Another instance is already recovering these changes.", Some(e))),
+            };
+            match self.recovery.load() {
+                Ok((mut set, _)) => {
+                    set.lock = path.as_ref().and_then(|p| SetLock::acquire(p, false).ok()); // Otherwise it's read-only
+                    state.open_background_set(set, path);
+                    state.set.as_mut().unwrap().dirty = true; // The recovered changes still haven't been saved
+                    state.recovery_file = Some(file); // It's this instance's to replace or discard now
+                },
+                Err(e) => state.open_modal(ErrorModal::new("The unsaved changes could not be recovered.", Some(e))),
+            }
+            return
+        }
+        ui.same_line(0.0);
+        if ui.button(im_str!("Discard"), AUTO_SIZE) {
+            if let Err(e) = self.recovery.discard() {
+                state.open_modal(ErrorModal::new("The recovery file could not be deleted.", Some(e)));
+            }
+            return
+        }
+        state.open_modal(self)
+    }
+}
+
+impl RecoverSet {
+    pub fn new(recovery: Recovery) -> RecoverSet {
+        RecoverSet { recovery }
+    }
+}
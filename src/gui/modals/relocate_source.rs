@@ -0,0 +1,104 @@
+use std::path::PathBuf;
+use std::sync::mpsc::{self, Receiver, TryRecvError};
+use std::thread;
+
+use super::ModalInterface;
+use crate::gui::prelude::*;
+use crate::sources::{Relocation, CompareKey, KeyRelation};
+use crate::utils::OptionExt;
+
+use widgets::{BackgroundGrid, CardOriginalInfo};
+
+pub struct RelocateSource {
+    source: usize,
+    root: Option<PathBuf>,
+    plan: Option<Relocation>,
+    /// The plan for `root` while it's still being worked out on another thread.
+    planning: Option<Receiver<Relocation>>,
+}
+
+impl ModalInterface for RelocateSource {
+    fn id(&self) -> &str { "relocatesource" }
+    fn title(&self) -> &str { "Relocate source" }
+    fn display<T: Textures + ?Sized>(mut self, state: &mut GuiState, frame: Frame<T>) {
+        let Frame { ui, .. } = frame;
+        let set = state.set.as_mut().expect("Cannot relocate a source when no background set is open!");
+        let source = &set.sources[self.source];
+
+        if let Some(planning) = &self.planning {
+            match planning.try_recv() {
+                Ok(plan) => { self.plan = Some(plan); self.planning = None },
+                // The planner panicked, so there's nothing to show for this location.
+                Err(TryRecvError::Disconnected) => { self.root = None; self.planning = None },
+                Err(TryRecvError::Empty) => {},
+            }
+        }
+
+        let current_root = source.root().map(|r| r.to_string_lossy()).unwrap_or("(none)".into());
+        ui.input_text(im_str!("Current location"), &mut ImString::new(current_root)).read_only(true).build();
+        let display_root = self.root.deref().map(|r| r.to_string_lossy()).unwrap_or("(none)".into());
+        ui.input_text(im_str!("New location"), &mut ImString::new(display_root)).read_only(true).build();
+        ui.same_line(0.0);
+        if ui.button(im_str!("Choose..."), AUTO_SIZE) {
+            match utils::nfd_handler(nfd::open_pick_folder(None), "new location") {
+                Ok(Some(path)) => {
+                    let planner = source.plan_relocation(path.clone());
+                    let (sender, planning) = mpsc::channel();
+                    thread::spawn(move || { let _ = sender.send(planner()); });
+                    self.root = Some(path);
+                    self.plan = None;
+                    self.planning = Some(planning);
+                },
+                Err(modal) => { state.open_modal(modal); return }
+                _ => {},
+            }
+        }
+
+        if self.planning.is_some() {
+            ui.text("Looking for originals in the new location...");
+        }
+
+        if let Some(plan) = &self.plan {
+            let total = plan.matched.len() + plan.unmatched.len();
+            ui.text(format!("{} of {} originals were found in the new location.", plan.matched.len(), total));
+
+            let textures = &mut *frame.textures;
+            let source_id = self.source;
+            let affected_backgrounds = set.backgrounds.indices().collect::<Vec<_>>().into_iter().filter_map(|id| {
+                let background = &set.backgrounds[id];
+                if background.source != source_id { return None }
+                if plan.unmatched.iter().all(|k| k.compare(&background.original) == KeyRelation::Distinct) { return None }
+                Some((id, CardOriginalInfo::try_load_from_set(set, id, textures)))
+            }).collect::<Vec<_>>();
+
+            if !affected_backgrounds.is_empty() {
+                ui.text("The originals of the following backgrounds could not be found, and will be treated as deleted:");
+                ui.spacing();
+                let grid = BackgroundGrid {
+                    id: &im_str!("UnmatchedBackgrounds"),
+                    entries: affected_backgrounds,
+                    card_width: ui.current_font_size() * 25.0, // TODO: Is there a less arbitrary choice here
+                    max_size: [0.0, (ui.io().display_size[1] * 2.0 / 3.0) - (ui.window_content_region_min()[1] - ui.cursor_pos()[1])].into(),
+                };
+                ui.center_h(grid.size(ui).x);
+                grid.draw(set, reborrow_frame!(frame));
+            }
+        }
+
+        ui.spacing();
+
+        if ui.button_hack(im_str!("Relocate"), AUTO_SIZE, self.plan.is_some()) {
+            state.relocate_source(self.source, self.root.unwrap(), self.plan.unwrap());
+            return
+        }
+        ui.same_line(0.0);
+        if ui.button(im_str!("Cancel"), AUTO_SIZE) { return }
+        state.open_modal(self)
+    }
+}
+
+impl RelocateSource {
+    pub fn new(source: usize) -> RelocateSource {
+        RelocateSource { source, root: None, plan: None, planning: None }
+    }
+}
\ No newline at end of file
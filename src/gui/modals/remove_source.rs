@@ -48,6 +48,7 @@ impl ModalInterface for RemoveSource {
                     }
                 }
                 set.remove_source(self.0);
+                set.dirty = true;
             }
             Some(false) => return,
             None => state.open_modal(self),
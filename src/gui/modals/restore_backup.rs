@@ -0,0 +1,63 @@
+use std::path::PathBuf;
+
+use super::{ModalInterface, ErrorModal};
+use crate::gui::prelude::*;
+use crate::background::{BackgroundSet, Backup, OpenMode, SetLock};
+
+/// Offered when a set file can't be loaded but there are backups of it which might still be intact.
+pub struct RestoreBackup {
+    path: PathBuf,
+    error: String,
+    backups: Vec<Backup>,
+    selected: usize,
+}
+
+impl ModalInterface for RestoreBackup {
+    fn id(&self) -> &str { "restorebackup" }
+    fn title(&self) -> &str { "Restore from backup" }
+    fn display<T: Textures + ?Sized>(mut self, state: &mut GuiState, frame: Frame<T>) {
+        let Frame { ui, .. } = frame;
+        ui.text(im_str!("The background set at {} could not be loaded. Details: {}", self.path.to_string_lossy(), self.error));
+        ui.text("Would you like to open one of its backups instead?");
+        ui.separator();
+
+        for (i, backup) in self.backups.iter().enumerate() {
+            let label = im_str!("{}###Backup{}", backup.time.format("%Y-%m-%d %H:%M:%S"), i);
+            if Selectable::new(&label).selected(i == self.selected).build(ui) {
+                self.selected = i;
+            }
+        }
+        ui.separator();
+
+        if ui.button(im_str!("Restore"), AUTO_SIZE) {
+            let backup = &self.backups[self.selected];
+            // Saving the restored set should replace the broken file, not the backup, so the backup isn't locked.
+            match BackgroundSet::load(&backup.path, OpenMode::ReadOnly) {
+                Ok((mut set, _)) => {
+                    state.release_lock_on(&self.path);
+                    set.lock = SetLock::acquire(&self.path, false).ok(); // Otherwise it's read-only
+                    state.open_background_set(set, Some(self.path));
+                    state.set.as_mut().unwrap().dirty = true;
+                },
+                Err(e) => {
+                    let msg = format!("The backup at {} could not be loaded either.", backup.path.to_string_lossy());
+                    state.open_modal(ErrorModal::new(msg, Some(e)));
+                }
+            }
+            return
+        }
+        ui.same_line(0.0);
+        if ui.button(im_str!("Cancel"), AUTO_SIZE) { return }
+        state.open_modal(self)
+    }
+}
+
+impl RestoreBackup {
+    /// Returns `None` if there are no backups to offer.
+    pub fn new(path: PathBuf, error: impl std::fmt::Debug, backups: Vec<Backup>) -> Option<RestoreBackup> {
+        match backups.is_empty() {
+            true => None,
+            false => Some(RestoreBackup { path, error: format!("{:?}", error), backups, selected: 0 }),
+        }
+    }
+}
@@ -0,0 +1,47 @@
+use std::path::PathBuf;
+
+use super::ModalInterface;
+use crate::gui::prelude::*;
+use crate::background::{LockHolder, OpenMode};
+
+/// Shown when the set file the user tried to open is locked by someone else who may be editing it.
+pub struct SetLocked {
+    path: PathBuf,
+    holder: Option<LockHolder>,
+}
+
+impl ModalInterface for SetLocked {
+    fn id(&self) -> &str { "setlocked" }
+    fn title(&self) -> &str { "Background set in use" }
+    fn display<T: Textures + ?Sized>(self, state: &mut GuiState, frame: Frame<T>) {
+        let Frame { ui, .. } = frame;
+        ui.text(im_str!("The background set at {} is locked, so someone may be editing it.", self.path.to_string_lossy()));
+        match &self.holder {
+            Some(holder) => ui.text(im_str!("It is held by {}.", holder.describe())),
+            None => ui.text("The lock file couldn't be read, so it isn't known who holds it."),
+        }
+        ui.text("You can open it read-only, or take the lock if it was left behind by a crash.");
+
+        if ui.button(im_str!("Open read-only"), AUTO_SIZE) {
+            state.open_set_file(self.path, OpenMode::ReadOnly);
+            return
+        }
+        ui.same_line(0.0);
+        if ui.button(im_str!("Take the lock"), AUTO_SIZE) {
+            state.open_set_file(self.path, OpenMode::BreakLock);
+            return
+        }
+        if ui.is_item_hovered() {
+            ui.tooltip_text("If the set really is being edited, one person's changes will overwrite the other's.");
+        }
+        ui.same_line(0.0);
+        if ui.button(im_str!("Cancel"), AUTO_SIZE) { return }
+        state.open_modal(self)
+    }
+}
+
+impl SetLocked {
+    pub fn new(path: PathBuf, holder: Option<LockHolder>) -> SetLocked {
+        SetLocked { path, holder }
+    }
+}
@@ -0,0 +1,39 @@
+use super::ModalInterface;
+use crate::gui::prelude::*;
+use crate::gui::state::CloseAction;
+
+/// Asks whether to save the open set before it is closed by `action`.
+pub struct UnsavedChanges {
+    action: CloseAction,
+}
+
+impl ModalInterface for UnsavedChanges {
+    fn id(&self) -> &str { "unsavedchanges" }
+    fn title(&self) -> &str { "Unsaved changes" }
+    fn display<T: Textures + ?Sized>(self, state: &mut GuiState, frame: Frame<T>) {
+        let Frame { ui, .. } = frame;
+        let set = state.set.as_ref().expect("Cannot close a background set when none is open!");
+        ui.text(im_str!("Do you want to save the changes to '{}'?", set.name().unwrap_or("(unnamed set)")));
+        ui.text("If you don't, they will be lost.");
+
+        if ui.button(im_str!("Save"), AUTO_SIZE) {
+            if state.save_set() { state.close_for(self.action); }
+            return
+        }
+        ui.same_line(0.0);
+        if ui.button(im_str!("Don't save"), AUTO_SIZE) {
+            state.discard_recovery(); // The user doesn't want these changes back either
+            state.close_for(self.action);
+            return
+        }
+        ui.same_line(0.0);
+        if ui.button(im_str!("Cancel"), AUTO_SIZE) { return }
+        state.open_modal(self)
+    }
+}
+
+impl UnsavedChanges {
+    pub fn new(action: CloseAction) -> UnsavedChanges {
+        UnsavedChanges { action }
+    }
+}
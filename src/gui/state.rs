@@ -1,4 +1,6 @@
 use std::ops::{Deref, DerefMut};
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant};
 
 use crate::{
     background::*,
@@ -6,19 +8,35 @@ use crate::{
     gui::prelude::*
 };
 
-use modals::{Modal, RemoveSource, confirm_changes::*};
+use modals::{Modal, RemoveSource, RelocateSource, RecoverSet, RestoreBackup, ErrorModal, UnsavedChanges, CheckSet, SetLocked, RebuildProgress, AdoptImageFolder, PreparingReload, confirm_changes::*};
 use super::bglist::Filter;
 
 pub enum Operation {
     ReloadSource(usize),
+    VerifySource(usize),
+    SetWatching(usize, bool),
     RemoveSource(usize),
+    RelocateSource(usize),
     SelectBackground(usize),
     ChangeFlags(usize, DesktopBackgroundFlags),
 }
 
+/// Something which closes the open set, and so should give the user a chance to save it first.
+#[derive(Copy, Clone)]
+pub enum CloseAction {
+    NewSet,
+    OpenSet,
+    Exit,
+}
+
+/// How often the open set is autosaved to the recovery file.
+const AUTOSAVE_INTERVAL: Duration = Duration::from_secs(60);
+
 pub struct ActiveSet {
     pub set: BackgroundSet,
     pub image_cache: ImageCache<OriginalKey>,
+    pub path: Option<PathBuf>, // The file the set was loaded from or last saved to
+    pub dirty: bool, // Whether the set has changed since it was loaded or last saved
 }
 
 impl Deref for ActiveSet {
@@ -28,6 +46,13 @@ impl Deref for ActiveSet {
     }
 }
 
+impl ActiveSet {
+    /// Whether the set's file was opened without locking it, so saving there may fail.
+    pub fn read_only(&self) -> bool {
+        self.path.is_some() && self.lock.is_none()
+    }
+}
+
 impl DerefMut for ActiveSet {
     fn deref_mut(&mut self) -> &mut BackgroundSet {
         &mut self.set
@@ -39,6 +64,11 @@ pub struct GuiState {
     pub(in super) set: Option<ActiveSet>,
     pub(in super) filter: Filter,
     pub(in super) selected_background: Option<usize>,
+    pub(in super) watcher: SourceWatcher,
+    pub(in super) last_autosave: Instant,
+    pub(in super) recovery_file: Option<RecoveryFile>, // Where this instance last autosaved to
+    pub(in super) pending_close: Option<CloseAction>, // Requested while a modal was open
+    pub(in super) exiting: bool,
     pub(in super) debug: bool,
 }
 
@@ -49,21 +79,52 @@ impl Default for GuiState {
             set: None,
             filter: Default::default(),
             selected_background: None,
+            watcher: SourceWatcher::new(),
+            last_autosave: Instant::now(),
+            recovery_file: None,
+            pending_close: None,
+            exiting: false,
             debug: false,
         }
     }
 }
 
+impl GuiState {
+    /// Create the GUI state, offering to recover any unsaved work left over from a crash.
+    pub fn new() -> GuiState {
+        let mut state = GuiState::default();
+        if let Some(recovery) = Recovery::find() { state.open_modal(RecoverSet::new(recovery)); }
+        state
+    }
+
+    /// Call when the program is exiting normally, so that the next launch doesn't offer to recover the set.
+    pub fn shutdown(&mut self) {
+        self.modal = None; // Wait for any rebuild of the image folder to stop
+        self.discard_recovery();
+        self.set = None; // Release the lock on the set's file
+        remove_extracted_archives();
+    }
+}
+
 // Commands
 impl GuiState {
     pub(in super) fn apply(&mut self, operation: Operation) {
         match operation {
             Operation::ReloadSource(source) => self.reload_source(source),
+            Operation::VerifySource(source) => self.verify_source(source),
+            Operation::SetWatching(source, watch) => {
+                if let Some(set) = &mut self.set {
+                    set.sources[source].set_watching(watch);
+                    set.dirty = true;
+                }
+            }
             Operation::RemoveSource(source) => self.open_modal(RemoveSource(source)),
+            Operation::RelocateSource(source) => self.open_modal(RelocateSource::new(source)),
             Operation::SelectBackground(background) => self.select_background(background),
             Operation::ChangeFlags(background, flags) => {
                 if let Some(set) = &mut self.set {
                     set.backgrounds[background].flags = flags;
+                    set.dirty = true;
                 }
             }
         }
@@ -72,14 +133,90 @@ impl GuiState {
     pub(in super) fn add_source<S: for<'s> DesktopBackgroundSource<'s> + 'static>(&mut self, source: S) {
         let set = self.set.as_mut().expect("Cannot add source when no background set is open!");
         let id = set.add_source(source);
+        set.dirty = true;
         let mut result_cache = ResultCache::new();
         result_cache.put::<()>(&ChangeKind::New, ChangeResult::Accept, false);
-        ConfirmChanges::new(id, set.sources[id].reload(), result_cache).apply_many(self);
+        self.start_reload(id, false, result_cache);
     }
 
     pub(in super) fn reload_source(&mut self, id: usize) {
+        self.start_reload(id, false, ResultCache::new());
+    }
+
+    pub(in super) fn verify_source(&mut self, id: usize) {
+        self.start_reload(id, true, ResultCache::new());
+    }
+
+    /// Reload or verify a source, first doing any slow preparation it needs on another thread.
+    fn start_reload(&mut self, id: usize, deep: bool, result_cache: ResultCache) {
+        let set = self.set.as_ref().expect("Cannot reload source when no background set is open!");
+        match set.sources[id].prepare_reload() {
+            Some(preparation) => self.open_modal(PreparingReload::new(id, deep, result_cache, preparation)),
+            None => self.finish_reload(id, deep, result_cache),
+        }
+    }
+
+    pub(in super) fn finish_reload(&mut self, id: usize, deep: bool, result_cache: ResultCache) {
         let set = self.set.as_mut().expect("Cannot reload source when no background set is open!");
-        ConfirmChanges::new(id, set.sources[id].reload(), ResultCache::new()).apply_many(self);
+        let source = &mut set.sources[id];
+        let changes = if deep { source.verify() } else { source.reload() };
+        set.dirty |= !changes.is_empty();
+        if changes.is_empty() {
+            // With nothing to confirm, an error which stopped the source from finding anything would go unnoticed.
+            if let Some(error) = set.sources[id].reload_error() {
+                let msg = format!("The source {} could not be reloaded. {}", set.sources[id].name(), error);
+                self.open_modal(ErrorModal::new(msg, None::<()>));
+                return
+            }
+        }
+        ConfirmChanges::new(id, changes, result_cache).apply_many(self);
+    }
+
+    pub(in super) fn relocate_source(&mut self, id: usize, root: PathBuf, plan: Relocation) {
+        let set = self.set.as_mut().expect("Cannot relocate source when no background set is open!");
+        let changes = set.sources[id].relocate(root, plan);
+        set.dirty = true;
+        ConfirmChanges::new(id, changes, ResultCache::new()).apply_many(self);
+    }
+
+    /// Reload any watched source whose files have changed, unless the user is busy with a modal.
+    pub(in super) fn check_watcher(&mut self) {
+        if let Some(set) = &self.set { self.watcher.update(set); }
+        if self.modal.is_none() {
+            if let Some(source) = self.watcher.take_ready() { self.reload_source(source); }
+        }
+    }
+
+    /// Autosave the open set to the recovery file if it has unsaved changes and it's been long enough since the last autosave.
+    pub(in super) fn check_autosave(&mut self) {
+        if self.last_autosave.elapsed() < AUTOSAVE_INTERVAL { return }
+        self.last_autosave = Instant::now();
+        let set = match self.set.as_ref().filter(|s| s.dirty) { Some(set) => set, None => return };
+        let set_path = set.path.as_ref().map(PathBuf::as_path);
+        // The set may have been saved somewhere else, or another one opened, since the last autosave. If so, the old
+        // recovery file is only removed once the new one has been written.
+        let (file, old) = match self.recovery_file.take() {
+            Some(file) if file.belongs_to(set_path) => (file, None),
+            old => match RecoveryFile::claim(set_path) {
+                Ok(file) => (file, old),
+                Err(_) => { self.recovery_file = old; return },
+            },
+        };
+        // Failing to autosave isn't worth interrupting the user for, and the next attempt may well succeed.
+        match set.save_recovery(set_path, &file) {
+            Ok(()) => {
+                if let Some(old) = old { let _ = old.remove(); }
+                self.recovery_file = Some(file);
+            },
+            Err(_) => self.recovery_file = Some(old.unwrap_or(file)),
+        }
+    }
+
+    /// Delete this instance's autosave, once there's nothing in it that needs recovering.
+    pub(in super) fn discard_recovery(&mut self) {
+        if let Some(file) = self.recovery_file.take() {
+            let _ = file.remove();
+        }
     }
 
     // TODO: Support multiple selection?
@@ -88,9 +225,159 @@ impl GuiState {
         self.selected_background = if self.selected_background != Some(background) { Some(background) } else { None }
     }
 
-    // TODO: Prompt, save current set.
-    pub(in super) fn open_background_set(&mut self, set: BackgroundSet) {
-        self.set = Some(ActiveSet { set, image_cache: ImageCache::new() });
+    /// Close the open set to do `action`, first asking the user whether to save it if it has unsaved changes. If a
+    /// modal is open, e.g. while the image folder is rebuilt, this waits until it's closed.
+    pub(in super) fn request_close(&mut self, action: CloseAction) {
+        match &self.modal {
+            Some(Modal::UnsavedChanges(_)) => return, // They're already being asked
+            Some(_) => { self.pending_close = Some(action); return },
+            None => {},
+        }
+        match &self.set {
+            Some(set) if set.dirty => self.open_modal(UnsavedChanges::new(action)),
+            _ => self.close_for(action),
+        }
+    }
+
+    /// Do `action` without checking for unsaved changes.
+    pub(in super) fn close_for(&mut self, action: CloseAction) {
+        match action {
+            CloseAction::NewSet => self.open_background_set(BackgroundSet::new(crate::utils::primary_monitor_resolution()), None),
+            CloseAction::OpenSet => self.choose_background_set(),
+            CloseAction::Exit => self.exiting = true,
+        }
+    }
+
+    /// Ask the user for a set file and open it.
+    fn choose_background_set(&mut self) {
+        let path = match utils::nfd_handler(nfd::open_file_dialog(Some("bgs;bgsb"), None), "background set") {
+            Ok(Some(path)) => path,
+            Err(modal) => { self.open_modal(modal); return }
+            _ => return,
+        };
+        self.open_set_file(path, OpenMode::Edit);
+    }
+
+    /// Release the open set's lock on the file at `path`, if it holds one, because a set from that file is about to
+    /// replace it. Returns whether it did.
+    pub(in super) fn release_lock_on(&mut self, path: &Path) -> bool {
+        match self.set.as_mut() {
+            Some(set) if set.lock.as_ref().map_or(false, |lock| lock.set_path() == path) => { set.lock = None; true },
+            _ => false,
+        }
+    }
+
+    /// Open the set file at `path`, telling the user if it's locked or can't be loaded.
+    pub(in super) fn open_set_file(&mut self, path: PathBuf, mode: OpenMode) {
+        let reopening = self.release_lock_on(&path);
+        let result = BackgroundSet::load(&path, mode);
+        if reopening && result.is_err() {
+            // The open set is staying open after all, so it should keep its lock too.
+            if let Some(set) = self.set.as_mut() { set.lock = SetLock::acquire(&path, false).ok(); }
+        }
+        match result {
+            Ok((set, warnings)) => {
+                self.open_background_set(set, Some(path));
+                let newer_version = warnings.iter().filter_map(|w| match w {
+                    SetLoadWarning::NewerVersion { version } => Some(*version),
+                    _ => None,
+                }).next();
+                if let Some(version) = newer_version {
+                    let msg = format!("This background set was saved by a newer version of the program (format version {}, \
+                        but only up to {} is supported), so some of it may not have loaded correctly. It has been opened \
+                        read-only, as saving over it would lose anything this version didn't understand. Use Save as \
+                        to save a copy somewhere else.", version, SET_FORMAT_VERSION);
+                    self.open_modal(ErrorModal::new(msg, None::<()>));
+                } else if warnings.iter().any(|w| match w { SetLoadWarning::CorruptOriginalKey { .. } => true, _ => false }) {
+                    self.open_modal(CheckSet::new(self.set.as_ref().unwrap().to_saved()));
+                }
+            },
+            Err(crate::background::Error::Locked(holder)) => self.open_modal(SetLocked::new(path, holder)),
+            Err(e) => match RestoreBackup::new(path.clone(), &e, list_backups(&path)) {
+                Some(modal) => self.open_modal(modal),
+                None => {
+                    let msg = format!("The background set at {} could not be loaded.", path.to_string_lossy());
+                    self.open_modal(ErrorModal::new(msg, Some(e)));
+                }
+            }
+        }
+    }
+
+    /// Save the open set to the file it came from, or ask where to save it if it doesn't have one yet.
+    /// Returns whether the set was saved.
+    pub(in super) fn save_set(&mut self) -> bool {
+        match self.set.as_ref().and_then(|s| s.path.clone()) {
+            Some(path) => self.save_set_to(path),
+            None => self.save_set_as(),
+        }
+    }
+
+    /// Ask the user where to save the open set, and save it there. Returns whether the set was saved.
+    pub(in super) fn save_set_as(&mut self) -> bool {
+        if !self.check_saveable() { return false }
+        match utils::nfd_handler(nfd::open_save_dialog(Some("bgs;bgsb"), None), "save location") {
+            Ok(Some(path)) => self.save_set_to(path),
+            Err(modal) => { self.open_modal(modal); false },
+            _ => false,
+        }
+    }
+
+    fn save_set_to(&mut self, path: PathBuf) -> bool {
+        if !self.check_saveable() { return false }
+        let set = self.set.as_mut().expect("Cannot save when no background set is open!");
+        match set.save(&path) {
+            Ok(_) => {
+                set.path = Some(path);
+                set.dirty = false;
+                self.discard_recovery(); // Everything in it has just been saved.
+                true
+            },
+            Err(crate::background::Error::Locked(holder)) => {
+                let msg = format!("The background set can't be saved to {} because {} is editing it. \
+                    Use Save as to save a copy somewhere else.", path.to_string_lossy(),
+                    holder.map(|h| h.describe()).unwrap_or_else(|| "someone else".to_owned()));
+                self.open_modal(ErrorModal::new(msg, None::<()>));
+                false
+            },
+            Err(crate::background::Error::NewerVersion(version)) => {
+                let msg = format!("The background set can't be saved to {} because that file was saved by a newer version \
+                    of the program (format version {}), and saving over it would lose anything this version doesn't \
+                    understand. Use Save as to save a copy somewhere else.", path.to_string_lossy(), version);
+                self.open_modal(ErrorModal::new(msg, None::<()>));
+                false
+            },
+            Err(e) => {
+                self.open_modal(ErrorModal::new("An error occured while saving the background set.", Some(e)));
+                false
+            }
+        }
+    }
+
+    /// Sets can't be saved without a name and an image folder, so tell the user if they're missing.
+    fn check_saveable(&mut self) -> bool {
+        let set = self.set.as_ref().expect("Cannot save when no background set is open!");
+        if set.name().is_some() && set.image_folder().is_some() { return true }
+        self.open_modal(ErrorModal::new(
+            "The background set needs a name and an image folder before it can be saved. They can be chosen under Set > Edit set information.",
+            None::<()>
+        ));
+        false
+    }
+
+    /// Start rebuilding the open set's image folder, showing its progress. If the folder has someone else's files in
+    /// it, the user is asked first unless `adopt_folder` is set.
+    pub(in super) fn rebuild_image_folder(&mut self, adopt_folder: bool) {
+        let set = self.set.as_ref().expect("Cannot rebuild the image folder when no background set is open!");
+        match set.start_rebuild(adopt_folder) {
+            Ok(rebuild) => self.open_modal(RebuildProgress::new(rebuild)),
+            Err(RebuildError::UnownedFolder) => self.open_modal(AdoptImageFolder),
+            Err(e) => self.open_modal(ErrorModal::new("An error occured while rebuilding the image folder.", Some(e))),
+        }
+    }
+
+    pub(in super) fn open_background_set(&mut self, set: BackgroundSet, path: Option<PathBuf>) {
+        self.set = Some(ActiveSet { set, image_cache: ImageCache::new(), path, dirty: false });
         self.selected_background = None;
+        self.watcher = SourceWatcher::new(); // Source IDs aren't meaningful across sets
     }
 }
\ No newline at end of file
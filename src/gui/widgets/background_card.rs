@@ -9,7 +9,7 @@ pub struct CardOriginalInfo { pub texture: Option<Texture>, pub location: String
 // TODO: See about deduplicating some of the code in these two functions. There's only a slight semantic difference.
 impl CardOriginalInfo {
     pub fn try_load_from_set<T: Textures + ?Sized>(set: &mut ActiveSet, id: usize, textures: &mut T) -> Option<CardOriginalInfo> {
-        let ActiveSet { set, image_cache } = set;
+        let ActiveSet { set, image_cache, .. } = set;
         let background = &mut set.backgrounds[id];
         let original = set.sources[background.source].original(&background.original);
         if let OriginalResult::Original(original) = original {
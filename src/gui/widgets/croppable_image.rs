@@ -15,14 +15,18 @@ impl CroppableImage {
         }
     }
 
-    pub fn build(self, ui: &Ui, mut region: EditableCropRegion) {
+    /// Draw the image and let the user move the crop region around it. Returns whether the region was changed.
+    pub fn build(self, ui: &Ui, mut region: EditableCropRegion) -> bool {
         let base = vec2![1.0, 1.0] + ui.cursor_pos() + ui.window_pos();
         Image::new(self.texture.id, self.size.into()).border_col(ui.style_color(StyleColor::Border)).build(ui);
+        let mut changed = false;
         if ui.is_item_hovered() {
             if ui.is_mouse_down(MouseButton::Left) {
                 *region.center = self.window_coord_to_tex(base, ui.io().mouse_pos);
+                changed = true;
             }
             *region.scale += ui.io().mouse_wheel / 100.0;
+            changed |= ui.io().mouse_wheel != 0.0;
             region.clip();
         }
         let top_left = self.tex_coord_to_window(base, region.top_left()).floor();
@@ -31,6 +35,7 @@ impl CroppableImage {
         let draw_list = ui.get_window_draw_list();
         draw_list.add_rect(top_left.into(), bottom_right.into(), [1.0, 0.0, 0.0]).build();
         draw_list.add_circle(center.into(), 10.0, [0.8, 0.8, 0.8]).build();
+        changed
     }
 
     fn tex_coord_to_window(&self, base: impl Into<Vec2>, point: impl Into<Vec2>) -> Vec2 {
@@ -15,12 +15,15 @@ use utils::check_result;
 fn main() -> Result<(), std::io::Error> {
     unsafe { check_result(combaseapi::CoInitializeEx(ptr::null_mut(), objbase::COINIT_APARTMENTTHREADED))?; }
     let mut renderer = renderer::init("Desktop Background Manager");
-    let mut state = gui::GuiState::default();
+    let mut state = gui::GuiState::new();
     let resources = gui::GuiResources::load(&mut renderer.render_sys.textures());
     renderer.main_loop(|run, ui, textures| {
         let frame = gui::draw::Frame { ui, textures, resources: &resources };
-        *run = *run && gui::draw::draw_state(&mut state, frame);
+        // The renderer stops running when the window is closed, but the GUI may want to ask about unsaved changes first.
+        let close_requested = !*run;
+        *run = gui::draw::draw_state(&mut state, frame, close_requested);
     });
+    state.shutdown();
     unsafe { combaseapi::CoUninitialize(); }
     Ok(())
 }
\ No newline at end of file
@@ -0,0 +1,333 @@
+use std::{
+    collections::HashMap,
+    path::{Path, PathBuf},
+    io::{self, Read, Seek, SeekFrom, BufReader, ErrorKind},
+    fs::{self, File},
+    rc::Rc,
+    sync::Mutex,
+};
+
+use image::{ImageResult, DynamicImage};
+use serde::{Serialize, Deserialize};
+
+use super::*;
+use super::folder::{FileStamp, HASH_SIZE};
+
+/// How much of each entry is read to tell whether it's an image. This is more than any format's magic number needs.
+const FORMAT_HEADER_SIZE: u64 = 64;
+
+/// A source which reads originals straight out of a zip or tar archive, without extracting it.
+#[derive(Serialize, Deserialize)]
+pub struct ArchiveSource {
+    #[serde(with = "crate::utils::portable_path")]
+    archive: PathBuf,
+    name: String,
+    format: ArchiveFormat,
+    stamp: Option<FileStamp>, // The archive's metadata when it was last scanned
+    originals: HashMap<String, ArchiveEntry>, // Keyed by path within the archive
+}
+
+#[derive(Copy, Clone, PartialEq, Eq, Debug, Serialize, Deserialize)]
+pub enum ArchiveFormat { Zip, Tar, TarGz }
+
+impl ArchiveFormat {
+    /// Guess the format of an archive from its file extension.
+    pub fn detect(path: &Path) -> Option<ArchiveFormat> {
+        let name = path.file_name()?.to_string_lossy().to_lowercase();
+        if name.ends_with(".zip") { Some(ArchiveFormat::Zip) }
+        else if name.ends_with(".tar") { Some(ArchiveFormat::Tar) }
+        else if name.ends_with(".tar.gz") || name.ends_with(".tgz") { Some(ArchiveFormat::TarGz) }
+        else { None }
+    }
+
+    /// Call `visit` with the path and contents of each regular file in the archive, until it returns false. For tar
+    /// archives, it's also given where the file's contents are in the (decompressed) archive, as an offset and size.
+    fn visit_entries(self, archive: &Path, mut visit: impl FnMut(String, Option<(u64, u64)>, &mut dyn Read) -> bool) -> Result<(), ArchiveError> {
+        let file = BufReader::new(File::open(archive)?);
+        match self {
+            ArchiveFormat::Zip => {
+                let mut zip = zip::ZipArchive::new(file)?;
+                for i in 0..zip.len() {
+                    let mut entry = zip.by_index(i)?;
+                    if entry.is_dir() { continue }
+                    let path = entry.name().to_owned();
+                    if !visit(path, None, &mut entry) { break }
+                }
+            },
+            ArchiveFormat::Tar => ArchiveFormat::visit_tar(tar::Archive::new(file), visit)?,
+            ArchiveFormat::TarGz => ArchiveFormat::visit_tar(tar::Archive::new(flate2::read::GzDecoder::new(file)), visit)?,
+        }
+        Ok(())
+    }
+
+    fn visit_tar<R: Read>(mut tar: tar::Archive<R>, mut visit: impl FnMut(String, Option<(u64, u64)>, &mut dyn Read) -> bool) -> io::Result<()> {
+        for entry in tar.entries()? {
+            let mut entry = entry?;
+            if !entry.header().entry_type().is_file() { continue }
+            let path = entry.path()?.to_string_lossy().into_owned();
+            let position = (entry.raw_file_position(), entry.size());
+            if !visit(path, Some(position), &mut entry) { break }
+        }
+        Ok(())
+    }
+}
+
+impl ArchiveSource {
+    pub fn new(archive: PathBuf, name: &str, format: ArchiveFormat) -> Self {
+        ArchiveSource {
+            archive: archive,
+            name: name.to_owned(),
+            format: format,
+            stamp: None,
+            originals: HashMap::new(),
+        }
+    }
+
+    /// Look for changes in the archive. Unless `deep` is set, the archive is assumed to be unchanged
+    /// if its size and modification time are the same as when it was last scanned.
+    fn rescan(&mut self, deep: bool) -> Vec<OriginalChange<ArchiveKey, Rc<ArchiveError>>> {
+        // Tar entries found before they were indexed need a scan to index them, even if the archive is unchanged.
+        let indexed = self.format == ArchiveFormat::Zip || self.originals.values().all(|e| e.index.is_some());
+        let stamp = match fs::metadata(&self.archive).map(FileStamp::of) {
+            Ok(stamp) if !deep && indexed && self.stamp.as_ref().map(|s| s.matches(&stamp)).unwrap_or(false) => return Vec::new(),
+            Ok(stamp) => stamp,
+            Err(e) => return self.all_unavailable(e.into()),
+        };
+
+        let mut contents = HashMap::new();
+        let scanned = self.format.visit_entries(&self.archive, |path, position, reader| {
+            // Only the start of each entry is needed to tell if it's an image, and the rest is hashed as it's read.
+            let mut header = Vec::new();
+            if (&mut *reader).take(FORMAT_HEADER_SIZE).read_to_end(&mut header).is_ok() && image::guess_format(&header).is_ok() {
+                if let Ok(hash) = FolderSource::hash_file((&header[..]).chain(reader)) {
+                    let index = position.map(|(offset, size)| EntryIndex { archive: stamp.clone(), offset, size });
+                    contents.insert(path, (hash, index));
+                }
+            }
+            true
+        });
+        if let Err(e) = scanned { return self.all_unavailable(e) }
+
+        let mut changes = Vec::new();
+        let archive = &self.archive;
+        let format = self.format;
+        self.originals.retain(|path, entry| {
+            let key = ArchiveKey { path: path.clone(), hash: entry.hash };
+            match contents.remove(path) {
+                Some((hash, index)) if hash != entry.hash => {
+                    entry.hash = hash;
+                    entry.index = index;
+                    changes.push(OriginalChange { key: key, kind: ChangeKind::Altered });
+                    true
+                },
+                Some((_, index)) => { entry.index = index; true },
+                None => {
+                    changes.push(OriginalChange { key: key, kind: ChangeKind::Deleted });
+                    false
+                },
+            }
+        });
+
+        // Anything left in contents wasn't in the archive before.
+        for (path, (hash, index)) in contents {
+            self.originals.insert(path.clone(), ArchiveEntry {
+                archive: archive.clone(),
+                format: format,
+                path: path.clone(),
+                hash: hash,
+                index: index,
+            });
+            changes.push(OriginalChange { key: ArchiveKey { path, hash }, kind: ChangeKind::New });
+        }
+
+        self.stamp = Some(stamp);
+        detect_moves(changes, |key| key.hash)
+    }
+
+    fn all_unavailable(&self, error: ArchiveError) -> Vec<OriginalChange<ArchiveKey, Rc<ArchiveError>>> {
+        let error = Rc::new(error);
+        self.originals.iter().map(|(path, entry)| OriginalChange {
+            key: ArchiveKey { path: path.clone(), hash: entry.hash },
+            kind: ChangeKind::Unavailable(error.clone()),
+        }).collect()
+    }
+}
+
+impl<'a> DesktopBackgroundSource<'a> for ArchiveSource {
+    type Key = ArchiveKey;
+    type Error = Rc<ArchiveError>;
+    type Original = ArchiveEntry;
+
+    const TYPE_IDENT: &'static str = "archive";
+
+    fn name(&self) -> &str { &self.name }
+
+    fn original(&self, key: &Self::Key) -> OriginalResult<&Self::Original> {
+        match self.originals.get(&key.path) {
+            Some(entry) => match entry.hash == key.hash {
+                true => OriginalResult::Original(entry),
+                false => OriginalResult::ContentMismatch(entry),
+            },
+            None => OriginalResult::NotFound
+        }
+    }
+
+    fn reload(&mut self) -> Vec<OriginalChange<ArchiveKey, Rc<ArchiveError>>> {
+        self.rescan(false)
+    }
+
+    fn verify(&mut self) -> Vec<OriginalChange<ArchiveKey, Rc<ArchiveError>>> {
+        self.rescan(true)
+    }
+}
+
+register_source_type!(ArchiveSource);
+
+#[derive(Debug)]
+pub enum ArchiveError {
+    Io(io::Error),
+    Zip(zip::result::ZipError),
+}
+
+impl From<io::Error> for ArchiveError {
+    fn from(error: io::Error) -> ArchiveError {
+        ArchiveError::Io(error)
+    }
+}
+
+impl From<zip::result::ZipError> for ArchiveError {
+    fn from(error: zip::result::ZipError) -> ArchiveError {
+        ArchiveError::Zip(error)
+    }
+}
+
+#[derive(Hash, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ArchiveKey {
+    path: String, // The path of the entry within the archive
+    #[serde(with = "crate::utils::compact_hash")]
+    hash: [u8; HASH_SIZE],
+}
+
+impl CompareKey for ArchiveKey {
+    fn compare(&self, other: &Self) -> KeyRelation {
+        match (self.path == other.path, self.hash == other.hash) {
+            (false, _) => KeyRelation::Distinct,
+            (true, false) => KeyRelation::ContentMismatch,
+            (true, true) => KeyRelation::SameOriginal,
+        }
+    }
+}
+
+#[derive(Clone, Serialize, Deserialize)]
+pub struct ArchiveEntry {
+    #[serde(with = "crate::utils::portable_path")]
+    archive: PathBuf,
+    format: ArchiveFormat,
+    path: String,
+    #[serde(with = "crate::utils::compact_hash")]
+    hash: [u8; HASH_SIZE],
+    #[serde(default)] // Entries found before indexing was added are searched for until the archive is scanned again.
+    index: Option<EntryIndex>,
+}
+
+/// Where a tar entry's contents were in its archive when it was last scanned, so it can be read without searching.
+#[derive(Clone, Serialize, Deserialize)]
+struct EntryIndex {
+    archive: FileStamp, // The archive's metadata when it was scanned, since the index is wrong if it's changed
+    offset: u64, // In the decompressed archive
+    size: u64,
+}
+
+impl ArchiveEntry {
+    /// Read the entry's contents. Zip archives have an index of their own, and tar entries are read using theirs if
+    /// the archive hasn't changed since it was made. Otherwise, the archive is searched from the start.
+    fn read_data(&self) -> Result<Vec<u8>, ArchiveError> {
+        let mut data = Vec::new();
+        let stamp = fs::metadata(&self.archive).map(FileStamp::of)?;
+        let current_index = self.index.as_ref().filter(|index| index.archive.matches(&stamp));
+        let indexed_file = match (self.format, current_index) {
+            (ArchiveFormat::Zip, _) => {
+                let mut zip = zip::ZipArchive::new(BufReader::new(File::open(&self.archive)?))?;
+                zip.by_name(&self.path)?.read_to_end(&mut data)?;
+                return Ok(data)
+            },
+            (ArchiveFormat::Tar, Some(index)) => Some((self.archive.clone(), index)),
+            (ArchiveFormat::TarGz, Some(index)) => Some((extracted_copy(&self.archive, &stamp)?, index)),
+            (_, None) => None,
+        };
+        if let Some((file, index)) = indexed_file {
+            let mut file = File::open(file)?;
+            file.seek(SeekFrom::Start(index.offset))?;
+            file.take(index.size).read_to_end(&mut data)?;
+            return Ok(data)
+        }
+
+        let mut result = None;
+        self.format.visit_entries(&self.archive, |path, _, reader| {
+            if path != self.path { return true }
+            result = Some(reader.read_to_end(&mut data));
+            false
+        })?;
+        match result {
+            Some(result) => { result?; Ok(data) },
+            None => Err(io::Error::new(ErrorKind::NotFound, "The entry could not be found in the archive.").into()),
+        }
+    }
+}
+
+/// Decompressed copies of gzipped tar archives, with the archive's path and metadata when each was made. Reading
+/// an entry from a gzipped archive means decompressing everything before it, so the first entry read from each
+/// archive decompresses all of it, and the rest are read from the copy.
+static EXTRACTED: Mutex<Vec<(PathBuf, FileStamp, PathBuf)>> = Mutex::new(Vec::new());
+
+fn extracted_copy(archive: &Path, stamp: &FileStamp) -> io::Result<PathBuf> {
+    let mut extracted = EXTRACTED.lock().unwrap_or_else(|e| e.into_inner());
+    if let Some((_, _, copy)) = extracted.iter().find(|(a, s, _)| a == archive && s.matches(stamp)) {
+        return Ok(copy.clone())
+    }
+    // A copy of an older version of the archive is no use any more.
+    if let Some(i) = extracted.iter().position(|(a, _, _)| a == archive) {
+        let _ = fs::remove_file(extracted.remove(i).2);
+    }
+
+    let copy = std::env::temp_dir().join(format!("dbgm-{}-{}.tar", std::process::id(), base64::encode_config(
+        &FolderSource::hash_file(archive.to_string_lossy().as_bytes())?[..12], base64::URL_SAFE
+    )));
+    let mut decoder = flate2::read::GzDecoder::new(BufReader::new(File::open(archive)?));
+    if let Err(e) = io::copy(&mut decoder, &mut File::create(&copy)?) {
+        let _ = fs::remove_file(&copy);
+        return Err(e)
+    }
+    extracted.push((archive.to_owned(), stamp.clone(), copy.clone()));
+    Ok(copy)
+}
+
+/// Delete the decompressed copies of gzipped tar archives made while reading their entries.
+pub fn remove_extracted_archives() {
+    let mut extracted = EXTRACTED.lock().unwrap_or_else(|e| e.into_inner());
+    for (_, _, copy) in extracted.drain(..) {
+        let _ = fs::remove_file(copy);
+    }
+}
+
+impl Original for ArchiveEntry {
+    fn read_image(&self) -> ImageResult<DynamicImage> {
+        match self.read_data() {
+            Ok(data) => image::load_from_memory(&data),
+            Err(ArchiveError::Io(e)) => Err(e.into()),
+            Err(ArchiveError::Zip(e)) => Err(io::Error::new(ErrorKind::NotFound, e.to_string()).into()),
+        }
+    }
+
+    fn name(&self) -> String {
+        Path::new(&self.path).file_name().map(|n| n.to_string_lossy().to_string()).unwrap_or_else(|| self.path.clone())
+    }
+
+    fn location(&self) -> String {
+        format!("{} ({})", self.archive.to_string_lossy(), self.path)
+    }
+
+    fn boxed_clone(&self) -> Box<dyn Original + Send + Sync> {
+        Box::new(self.clone())
+    }
+}
@@ -0,0 +1,267 @@
+use std::{
+    any::Any,
+    collections::HashMap,
+    path::PathBuf,
+    io::{self, ErrorKind},
+    fs::{self, File},
+    process::{Command, ExitStatus},
+    sync::Arc,
+};
+
+use image::{ImageResult, DynamicImage};
+use rayon::prelude::*;
+use serde::{Serialize, Deserialize};
+
+use super::*;
+use super::folder::{FileStamp, HASH_SIZE};
+
+/// A source which runs an external command to find its originals. The command should print a JSON list of
+/// objects with `id`, `path`, `name` and `location` fields to standard output, where `id` uniquely and
+/// permanently identifies each original. `name` and `location` may be omitted.
+#[derive(Serialize, Deserialize)]
+pub struct CommandSource {
+    command: String, // Run through the platform's shell, so it may contain arguments
+    name: String,
+    originals: HashMap<String, CommandOriginal>, // Keyed by the id reported by the command
+    #[serde(skip)]
+    prepared: Option<Result<Vec<ListedOriginal>, CommandError>>, // The command's output, if it was run ahead of a reload
+    #[serde(skip)]
+    error: Option<String>, // Why the command failed the last time it was run
+}
+
+#[derive(Deserialize)]
+struct ListedOriginal {
+    id: String,
+    path: PathBuf,
+    name: Option<String>,
+    location: Option<String>,
+}
+
+impl CommandSource {
+    pub fn new(command: &str, name: &str) -> Self {
+        CommandSource {
+            command: command.to_owned(),
+            name: name.to_owned(),
+            originals: HashMap::new(),
+            prepared: None,
+            error: None,
+        }
+    }
+
+    fn shell_command(&self) -> Command {
+        let (shell, flag) = if cfg!(windows) { ("cmd", "/C") } else { ("sh", "-c") };
+        let mut command = Command::new(shell);
+        command.arg(flag).arg(&self.command);
+        command
+    }
+
+    fn run(mut command: Command) -> Result<Vec<ListedOriginal>, CommandError> {
+        let output = command.output().map_err(CommandError::Spawn)?;
+        let stderr = String::from_utf8_lossy(&output.stderr).into_owned();
+        if !output.status.success() {
+            return Err(CommandError::Failed { status: output.status, stderr })
+        }
+        serde_json::from_slice(&output.stdout).map_err(|error| CommandError::Malformed { error, stderr })
+    }
+
+    fn rescan(&mut self, deep: bool) -> Vec<OriginalChange<CommandKey, Arc<CommandError>>> {
+        let listed = self.prepared.take().unwrap_or_else(|| CommandSource::run(self.shell_command()));
+        self.error = listed.as_ref().err().map(CommandError::describe);
+        let listed = match listed {
+            Ok(listed) => listed,
+            Err(e) => {
+                let error = Arc::new(e);
+                return self.originals.iter().map(|(id, original)| OriginalChange {
+                    key: CommandKey { id: id.clone(), hash: original.hash },
+                    kind: ChangeKind::Unavailable(error.clone()),
+                }).collect()
+            }
+        };
+        let mut listed: HashMap<_, _> = listed.into_iter().map(|l| (l.id.clone(), l)).collect();
+
+        // Originals the command no longer lists count as deleted, even if the file itself still exists.
+        let mut changes = Vec::new();
+        self.originals.retain(|id, original| {
+            if listed.contains_key(id) { return true }
+            changes.push(OriginalChange { key: CommandKey { id: id.clone(), hash: original.hash }, kind: ChangeKind::Deleted });
+            false
+        });
+
+        // Check the originals we already have for changes, picking up any new names or paths on the way.
+        let checked = self.originals.par_iter_mut().filter_map(|(id, original)| {
+            let key = CommandKey { id: id.clone(), hash: original.hash };
+            let entry = &listed[id];
+            let moved = entry.path != original.path;
+            original.update_from(entry);
+            let stamp = match fs::metadata(&original.path).map(FileStamp::of) {
+                Ok(stamp) if !deep && !moved && original.stamp.as_ref().map(|s| s.matches(&stamp)).unwrap_or(false) => return None,
+                Ok(stamp) => stamp,
+                Err(e) => return Some(CommandSource::missing_change(key, e)),
+            };
+            match File::open(&original.path).and_then(FolderSource::hash_file) {
+                Ok(hash) if hash != original.hash => {
+                    original.hash = hash;
+                    original.stamp = Some(stamp);
+                    Some(OriginalChange { key: key, kind: ChangeKind::Altered })
+                },
+                Ok(_) => { original.stamp = Some(stamp); None },
+                Err(e) => Some(CommandSource::missing_change(key, e)),
+            }
+        }).collect::<Vec<_>>();
+
+        for change in &checked {
+            if let ChangeKind::Deleted = change.kind { self.originals.remove(&change.key.id); }
+        }
+        changes.extend(checked);
+
+        // Anything listed that we aren't tracking yet is new.
+        for id in self.originals.keys() { listed.remove(id); }
+        let new_originals = listed.into_par_iter().filter_map(|(id, entry)| {
+            image::image_dimensions(&entry.path).ok()?;
+            let stamp = fs::metadata(&entry.path).map(FileStamp::of).ok();
+            let hash = File::open(&entry.path).and_then(FolderSource::hash_file).ok()?;
+            let mut original = CommandOriginal { path: PathBuf::new(), name: None, location: None, hash, stamp };
+            original.update_from(&entry);
+            Some((id, original))
+        }).collect::<Vec<_>>();
+
+        for (id, original) in new_originals {
+            changes.push(OriginalChange { key: CommandKey { id: id.clone(), hash: original.hash }, kind: ChangeKind::New });
+            self.originals.insert(id, original);
+        }
+
+        changes
+    }
+
+    fn missing_change(key: CommandKey, error: io::Error) -> OriginalChange<CommandKey, Arc<CommandError>> {
+        match error.kind() {
+            ErrorKind::NotFound => OriginalChange { key: key, kind: ChangeKind::Deleted },
+            _ => OriginalChange { key: key, kind: ChangeKind::Unavailable(Arc::new(CommandError::Io(error))) },
+        }
+    }
+}
+
+impl<'a> DesktopBackgroundSource<'a> for CommandSource {
+    type Key = CommandKey;
+    type Error = Arc<CommandError>;
+    type Original = CommandOriginal;
+
+    const TYPE_IDENT: &'static str = "command";
+
+    fn name(&self) -> &str { &self.name }
+
+    fn original(&self, key: &Self::Key) -> OriginalResult<&Self::Original> {
+        match self.originals.get(&key.id) {
+            Some(original) => match original.hash == key.hash {
+                true => OriginalResult::Original(original),
+                false => OriginalResult::ContentMismatch(original),
+            },
+            None => OriginalResult::NotFound
+        }
+    }
+
+    fn reload(&mut self) -> Vec<OriginalChange<CommandKey, Arc<CommandError>>> {
+        self.rescan(false)
+    }
+
+    fn verify(&mut self) -> Vec<OriginalChange<CommandKey, Arc<CommandError>>> {
+        self.rescan(true)
+    }
+
+    fn prepare_reload(&self) -> Option<ReloadPreparation> {
+        let command = self.shell_command();
+        Some(Box::new(move || Box::new(CommandSource::run(command)) as Box<dyn Any + Send>))
+    }
+
+    fn accept_prepared(&mut self, prepared: Box<dyn Any + Send>) {
+        if let Ok(listed) = prepared.downcast() { self.prepared = Some(*listed); }
+    }
+
+    fn reload_error(&self) -> Option<String> {
+        self.error.clone()
+    }
+}
+
+register_source_type!(CommandSource);
+
+#[derive(Debug)]
+pub enum CommandError {
+    /// The command could not be started.
+    Spawn(io::Error),
+    /// The command exited unsuccessfully.
+    Failed { status: ExitStatus, stderr: String },
+    /// The command's output was not a valid list of originals.
+    Malformed { error: serde_json::Error, stderr: String },
+    /// An original listed by the command could not be read.
+    Io(io::Error),
+}
+
+impl CommandError {
+    /// A human-readable explanation of the error, including anything the command printed to standard error.
+    pub fn describe(&self) -> String {
+        let (message, stderr) = match self {
+            CommandError::Spawn(e) => (format!("The command could not be started: {}", e), ""),
+            CommandError::Failed { status, stderr } => (format!("The command failed ({}).", status), stderr.as_str()),
+            CommandError::Malformed { error, stderr } => (format!("The command's output was not a valid list of originals: {}", error), stderr.as_str()),
+            CommandError::Io(e) => (format!("An original could not be read: {}", e), ""),
+        };
+        match stderr.trim() {
+            "" => message,
+            stderr => format!("{}\n{}", message, stderr),
+        }
+    }
+}
+
+#[derive(Hash, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct CommandKey {
+    id: String,
+    #[serde(with = "crate::utils::compact_hash")]
+    hash: [u8; HASH_SIZE],
+}
+
+impl CompareKey for CommandKey {
+    fn compare(&self, other: &Self) -> KeyRelation {
+        match (self.id == other.id, self.hash == other.hash) {
+            (false, _) => KeyRelation::Distinct,
+            (true, false) => KeyRelation::ContentMismatch,
+            (true, true) => KeyRelation::SameOriginal,
+        }
+    }
+}
+
+#[derive(Clone, Serialize, Deserialize)]
+pub struct CommandOriginal {
+    #[serde(with = "crate::utils::portable_path")]
+    path: PathBuf,
+    name: Option<String>,
+    location: Option<String>,
+    #[serde(with = "crate::utils::compact_hash")]
+    hash: [u8; HASH_SIZE],
+    stamp: Option<FileStamp>, // The file's metadata when `hash` was last computed
+}
+
+impl CommandOriginal {
+    fn update_from(&mut self, entry: &ListedOriginal) {
+        self.path = entry.path.clone();
+        self.name = entry.name.clone();
+        self.location = entry.location.clone();
+    }
+}
+
+impl Original for CommandOriginal {
+    fn read_image(&self) -> ImageResult<DynamicImage> {
+        image::open(&self.path)
+    }
+
+    fn name(&self) -> String {
+        self.name.clone().unwrap_or_else(|| self.path.file_name().unwrap_or_default().to_string_lossy().to_string())
+    }
+
+    fn location(&self) -> String {
+        self.location.clone().unwrap_or_else(|| self.path.to_string_lossy().to_string())
+    }
+
+    fn boxed_clone(&self) -> Box<dyn Original + Send + Sync> {
+        Box::new(self.clone())
+    }
+}
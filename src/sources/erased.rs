@@ -1,12 +1,26 @@
 use super::*;
+use std::any::Any;
 use std::fmt::Debug;
 use std::hash::{Hash, Hasher};
+use std::path::{Path, PathBuf};
 
 pub trait ErasedDesktopBackgroundSource: erased_serde::Serialize {
     fn name(&self) -> &str;
     fn original(&self, id: &OriginalKey) -> OriginalResult<&dyn Original>;
     fn reload(&mut self) -> Vec<OriginalChange<OriginalKey, Box<dyn Debug>>>;
+    fn verify(&mut self) -> Vec<OriginalChange<OriginalKey, Box<dyn Debug>>>;
+    fn prepare_reload(&self) -> Option<ReloadPreparation>;
+    fn accept_prepared(&mut self, prepared: Box<dyn Any + Send>);
+    fn reload_error(&self) -> Option<String>;
+    fn root(&self) -> Option<&Path>;
+    fn plan_relocation(&self, root: PathBuf) -> RelocationPlanner;
+    fn relocate(&mut self, root: PathBuf, plan: Relocation) -> Vec<OriginalChange<OriginalKey, Box<dyn Debug>>>;
+    fn watching(&self) -> Option<bool>;
+    fn set_watching(&mut self, watch: bool);
+    fn watch_paths(&self) -> Vec<(PathBuf, bool)>;
     fn assemble_key(&self, value: serde_json::Value) -> OriginalKey;
+    /// Check that `value` is a valid key for this source, as `assemble_key` doesn't.
+    fn check_key(&self, value: &serde_json::Value) -> Result<(), serde_json::Error>;
     fn source_type_id(&self) -> &'static str;
     fn as_serialize(&self) -> &dyn erased_serde::Serialize;
 }
@@ -54,6 +68,10 @@ impl<'a> Into<serde_json::Value> for &'a OriginalKey {
     fn into(self) -> serde_json::Value { self.value.clone() }
 }
 
+impl Debug for OriginalKey {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result { self.value.fmt(f) }
+}
+
 impl CompareKey for OriginalKey { 
     fn compare(&self, other: &Self) -> KeyRelation { (self.vtable.comparer)(self, other) } 
 }
@@ -91,21 +109,55 @@ impl<S: for<'a> DesktopBackgroundSource<'a>> ErasedDesktopBackgroundSource for S
     }
 
     fn reload(&mut self) -> Vec<OriginalChange<OriginalKey, Box<dyn Debug>>> {
-        self.reload().into_iter().map(|c| OriginalChange {
-            key: OriginalKey::new::<S>(c.key),
-            kind: match c.kind {
-                ChangeKind::New => ChangeKind::New,
-                ChangeKind::Deleted => ChangeKind::Deleted,
-                ChangeKind::Altered => ChangeKind::Altered,
-                ChangeKind::Unavailable(e) => ChangeKind::Unavailable(Box::new(e) as Box<dyn Debug>),
+        erase_changes::<S>(self.reload())
+    }
+
+    fn verify(&mut self) -> Vec<OriginalChange<OriginalKey, Box<dyn Debug>>> {
+        erase_changes::<S>(self.verify())
+    }
+
+    fn prepare_reload(&self) -> Option<ReloadPreparation> { self.prepare_reload() }
+
+    fn accept_prepared(&mut self, prepared: Box<dyn Any + Send>) { self.accept_prepared(prepared) }
+
+    fn reload_error(&self) -> Option<String> { self.reload_error() }
+
+    fn root(&self) -> Option<&Path> { self.root() }
+
+    fn plan_relocation(&self, root: PathBuf) -> RelocationPlanner {
+        let planner = self.plan_relocation(root);
+        Box::new(move || {
+            let relocation = planner();
+            Relocation {
+                matched: relocation.matched.into_iter().map(|(old, new)| (OriginalKey::new::<S>(old), OriginalKey::new::<S>(new))).collect(),
+                unmatched: relocation.unmatched.into_iter().map(OriginalKey::new::<S>).collect(),
             }
-        }).collect()
+        })
     }
 
+    fn relocate(&mut self, root: PathBuf, plan: Relocation) -> Vec<OriginalChange<OriginalKey, Box<dyn Debug>>> {
+        // Keys which don't belong to this source can't have come from its plan, so they're left out.
+        let plan = Relocation {
+            matched: plan.matched.iter().filter_map(|(old, new)| Some((old.try_deserialize()?, new.try_deserialize()?))).collect(),
+            unmatched: plan.unmatched.iter().filter_map(OriginalKey::try_deserialize).collect(),
+        };
+        erase_changes::<S>(self.relocate(root, plan))
+    }
+
+    fn watching(&self) -> Option<bool> { self.watching() }
+
+    fn set_watching(&mut self, watch: bool) { self.set_watching(watch) }
+
+    fn watch_paths(&self) -> Vec<(PathBuf, bool)> { self.watch_paths() }
+
     fn assemble_key(&self, value: serde_json::Value) -> OriginalKey {
         OriginalKey { value: value, vtable: KeyVtable::of::<Self>() }
     }
 
+    fn check_key(&self, value: &serde_json::Value) -> Result<(), serde_json::Error> {
+        serde_json::from_value::<S::Key>(value.clone()).map(|_| ())
+    }
+
     fn source_type_id(&self) -> &'static str {
         Self::TYPE_IDENT
     }
@@ -113,16 +165,33 @@ impl<S: for<'a> DesktopBackgroundSource<'a>> ErasedDesktopBackgroundSource for S
     fn as_serialize(&self) -> &dyn erased_serde::Serialize { self }
 }
 
+fn erase_changes<S: DesktopBackgroundSource<'static>>(changes: Vec<OriginalChange<S::Key, S::Error>>) -> Vec<OriginalChange<OriginalKey, Box<dyn Debug>>> {
+    changes.into_iter().map(|c| OriginalChange {
+        key: OriginalKey::new::<S>(c.key),
+        kind: c.kind.map_err(|e| Box::new(e) as Box<dyn Debug>).map_key(OriginalKey::new::<S>),
+    }).collect()
+}
+
 #[doc(hidden)]
 pub struct SourceLoader(
     pub &'static str,
     pub Box<fn(v: serde_json::Value) -> Result<Box<dyn ErasedDesktopBackgroundSource>, serde_json::Error>>
 );
 
+/// Upgrades saved sources of the type with the given identifier from the given version to the next one.
+/// The function is passed the whole saved source, so it can update the keys of its backgrounds as well as its data.
+pub struct SourceMigration(
+    pub &'static str,
+    pub u32,
+    pub fn(&mut serde_json::Value)
+);
+
 #[derive(Debug)]
 pub enum SourceLoadError {
     Deserialize(serde_json::Error),
     IdNotFound,
+    /// The source was saved by a newer version which uses a format we don't understand.
+    NewerVersion { version: u32, supported: u32 },
 }
 
 impl From<serde_json::Error> for SourceLoadError {
@@ -141,4 +210,21 @@ pub fn load_source_by_id(id: &str, data: serde_json::Value) -> Result<Box<dyn Er
     Err(SourceLoadError::IdNotFound)
 }
 
-inventory::collect!(SourceLoader);
\ No newline at end of file
+/// The current version of the saved format for the source type with the given identifier. This is the number of
+/// migrations registered for it, so it starts at 0 and increases each time a migration is added.
+pub fn source_version(id: &str) -> u32 {
+    inventory::iter::<SourceMigration>.into_iter().filter(|m| m.0 == id).map(|m| m.1 + 1).max().unwrap_or(0)
+}
+
+/// Apply migrations to a saved source of the given type until it reaches the current version, returning the
+/// version it ended up at.
+pub fn migrate_source(id: &str, mut version: u32, saved: &mut serde_json::Value) -> u32 {
+    while let Some(migration) = inventory::iter::<SourceMigration>.into_iter().find(|m| m.0 == id && m.1 == version) {
+        (migration.2)(saved);
+        version += 1;
+    }
+    version
+}
+
+inventory::collect!(SourceLoader);
+inventory::collect!(SourceMigration);
\ No newline at end of file
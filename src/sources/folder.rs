@@ -1,41 +1,226 @@
 use std::{
-    collections::HashMap,
-    path::PathBuf,
+    collections::{HashMap, HashSet},
+    path::{Path, PathBuf},
     ffi::OsString,
     io::{self, Read, ErrorKind},
-    fs::{self, File},
+    fs::{self, File, Metadata},
+    time::SystemTime,
 };
 
+use glob::{Pattern, PatternError, MatchOptions};
 use image::{ImageResult, DynamicImage};
+use rayon::prelude::*;
 use serde::{Serialize, Deserialize};
+use walkdir::WalkDir;
 
 use super::*;
 
 pub const HASH_SIZE: usize = 32;
+const HASH_CHUNK_SIZE: usize = 64 * 1024;
 
 #[derive(Serialize, Deserialize)]
 pub struct FolderSource {
+    #[serde(with = "crate::utils::portable_path")]
     folder: PathBuf,
     name: String,
-    #[serde(with = "crate::utils::as_pairs")] // OsStrings can't be used as JSON keys, so we save a list of pairs
-    originals: HashMap<OsString, OriginalFile>,
+    #[serde(default)] // Sets saved before scan options existed only scanned the top level.
+    scan: ScanOptions,
+    #[serde(default)]
+    watch: bool,
+    #[serde(with = "crate::utils::portable_path::pairs")]
+    originals: HashMap<OsString, OriginalFile>, // Keyed by path relative to `folder`
+}
+
+/// Controls which files inside a `FolderSource`'s folder are considered originals.
+#[derive(Clone, Default, Debug, Serialize, Deserialize)]
+pub struct ScanOptions {
+    /// Whether to scan subfolders as well as the top level of the folder.
+    pub recursive: bool,
+    /// How many levels of subfolders to descend into when scanning recursively. `None` means no limit.
+    pub max_depth: Option<usize>,
+    /// Glob patterns matched against paths relative to the folder. If any are given, a file must match one.
+    pub include: Vec<String>,
+    /// Glob patterns matched against paths relative to the folder. Files matching any of these are skipped.
+    pub exclude: Vec<String>,
+}
+
+impl ScanOptions {
+    /// Check that all include and exclude patterns are valid globs.
+    pub fn validate(&self) -> Result<(), PatternError> {
+        self.include.iter().chain(&self.exclude).map(|p| Pattern::new(p).map(|_| ())).collect()
+    }
+
+    fn compile(patterns: &[String]) -> Vec<Pattern> {
+        // Invalid patterns are rejected by `validate` before a source is created, so we can skip them here.
+        patterns.iter().filter_map(|p| Pattern::new(p).ok()).collect()
+    }
+
+    /// List the files in `folder` which pass these options, keyed by their path relative to `folder`.
+    fn scan(&self, folder: &Path) -> HashMap<OsString, PathBuf> {
+        let max_depth = match (self.recursive, self.max_depth) {
+            (false, _) => 1,
+            (true, Some(depth)) => depth.saturating_add(1),
+            (true, None) => usize::max_value(),
+        };
+        let (include, exclude) = (ScanOptions::compile(&self.include), ScanOptions::compile(&self.exclude));
+        let options = MatchOptions { require_literal_separator: true, ..MatchOptions::new() };
+        let matches = |patterns: &[Pattern], path: &Path| patterns.iter().any(|p| p.matches_path_with(path, options));
+
+        WalkDir::new(folder).min_depth(1).max_depth(max_depth).follow_links(true).into_iter()
+            .filter_map(|r| r.ok())
+            .filter(|e| e.file_type().is_file())
+            .filter_map(|e| {
+                let relative = e.path().strip_prefix(folder).ok()?.to_owned();
+                if !include.is_empty() && !matches(&include, &relative) { return None }
+                if matches(&exclude, &relative) { return None }
+                Some((relative.into_os_string(), e.into_path()))
+            })
+            .collect()
+    }
 }
 
 impl FolderSource {
-    pub fn new(folder: PathBuf, name: &str) -> Self {
+    pub fn new(folder: PathBuf, name: &str, scan: ScanOptions) -> Self {
         FolderSource {
             folder: folder,
             name: name.to_owned(),
+            scan: scan,
+            watch: false,
             originals: HashMap::new(),
         }
     }
 
-    fn hash_file(mut file: File) -> io::Result<[u8; HASH_SIZE]> {
+    /// List the files in the folder which pass the scan options, keyed by their path relative to the folder.
+    fn scan_folder(&self) -> HashMap<OsString, PathBuf> {
+        self.scan.scan(&self.folder)
+    }
+
+    /// Look for changes in the folder. Unless `deep` is set, originals whose size and modification time
+    /// haven't changed since they were last hashed are assumed to be unaltered.
+    fn rescan(&mut self, deep: bool) -> Vec<OriginalChange<FileKey, std::io::Error>> {
+        let contents = self.scan_folder();
+        detect_moves(FolderSource::diff_files(&mut self.originals, contents, deep), |key| key.hash)
+    }
+
+    /// Check a set of tracked files for changes, and start tracking any image files in `contents` that
+    /// aren't already tracked. Both maps are keyed by the path stored in each file's `FileKey`.
+    pub(super) fn diff_files(
+        originals: &mut HashMap<OsString, OriginalFile>, 
+        mut contents: HashMap<OsString, PathBuf>, 
+        deep: bool
+    ) -> Vec<OriginalChange<FileKey, std::io::Error>> {
+        for (relative, original) in originals.iter_mut() {
+            // Where a tracked file is can change without its key changing, e.g. when a list source's list is moved.
+            if let Some(path) = contents.remove(relative) { original.path = path; }
+        }
+
+        // Check all the originals we already have for changes. Hashing is spread across the rayon thread pool.
+        let mut changes = originals.par_iter_mut().filter_map(|(relative, original)| {
+            let key = FileKey { path: relative.clone(), hash: original.hash };
+            let stamp = match fs::metadata(&original.path).map(FileStamp::of) {
+                Ok(stamp) if !deep && original.stamp.as_ref().map(|s| s.matches(&stamp)).unwrap_or(false) => return None,
+                Ok(stamp) => stamp,
+                Err(e) => return Some(FolderSource::missing_change(key, e)),
+            };
+            match File::open(&original.path).and_then(FolderSource::hash_file) {
+                Ok(hash) if hash != original.hash => {
+                    original.hash = hash; // TODO: Figure out how to deal with the two hashes
+                    original.stamp = Some(stamp);
+                    Some(OriginalChange { key: key, kind: ChangeKind::Altered })
+                },
+                Ok(_) => { original.stamp = Some(stamp); None },
+                Err(e) => Some(FolderSource::missing_change(key, e)),
+            }
+        }).collect::<Vec<_>>();
+
+        for change in &changes {
+            if let ChangeKind::Deleted = change.kind { originals.remove(&change.key.path); }
+        }
+        
+        // We've removed all existing originals, anything left in contents is new.
+        let new_originals = contents.into_par_iter().filter_map(|(relative, path)| {
+            // TODO: We could go purely by extension here, and say that other files are corrupted
+            // instead of silently ignoring them. Alternatively, logging for people who care.
+            // Only the image header is decoded here, the full image is read when it's actually needed.
+            image::image_dimensions(&path).ok()?;
+            let stamp = fs::metadata(&path).map(FileStamp::of).ok();
+            let hash = File::open(&path).and_then(FolderSource::hash_file).ok()?;
+            Some((relative, OriginalFile { mismatch: false, path: path, hash: hash, stamp: stamp }))
+        }).collect::<Vec<_>>();
+
+        for (relative, original) in new_originals {
+            changes.push(OriginalChange { 
+                key: FileKey { path: relative.clone(), hash: original.hash }, kind: ChangeKind::New
+            });
+            originals.insert(relative, original);
+        }
+
+        changes
+    }
+
+    /// Find where each of `originals`, given as its relative path and hash, would be if the folder was moved to
+    /// `root`, by looking for a file with the same name and content. Files at the same relative path are tried first.
+    /// Each file is matched to at most one original, so when identical originals compete for a file, only the first
+    /// to claim it is matched and the rest are left unmatched.
+    fn match_relocation(scan: &ScanOptions, root: &Path, mut originals: Vec<(OsString, [u8; HASH_SIZE])>) -> Relocation<FileKey> {
+        let file_name = |relative: &OsString| Path::new(relative).file_name().unwrap_or_default().to_owned();
+        let names = originals.iter().map(|(relative, _)| file_name(relative)).collect::<HashSet<_>>();
+        // Each candidate is only hashed once, however many originals share its name.
+        let mut candidates = scan.scan(root).into_par_iter()
+            .filter(|(relative, _)| names.contains(&file_name(relative)))
+            .filter_map(|(relative, path)| Some((relative, File::open(&path).and_then(FolderSource::hash_file).ok()?)))
+            .collect::<Vec<_>>();
+        candidates.sort();
+        originals.sort();
+
+        let mut claimed = HashSet::new();
+        let mut found = HashMap::new();
+        for (relative, hash) in &originals {
+            if candidates.iter().any(|(r, h)| r == relative && h == hash) && claimed.insert(relative.clone()) {
+                found.insert(relative.clone(), relative.clone());
+            }
+        }
+        for (relative, hash) in &originals {
+            if found.contains_key(relative) { continue }
+            let name = file_name(relative);
+            let candidate = candidates.iter()
+                .find(|(r, h)| h == hash && file_name(r) == name && !claimed.contains(r));
+            if let Some((new_relative, _)) = candidate {
+                claimed.insert(new_relative.clone());
+                found.insert(relative.clone(), new_relative.clone());
+            }
+        }
+
+        let mut relocation = Relocation::default();
+        for (relative, hash) in originals {
+            match found.remove(&relative) {
+                Some(new_relative) => relocation.matched.push((FileKey { path: relative, hash }, FileKey { path: new_relative, hash })),
+                None => relocation.unmatched.push(FileKey { path: relative, hash }),
+            }
+        }
+        relocation
+    }
+
+    fn missing_change(key: FileKey, error: io::Error) -> OriginalChange<FileKey, io::Error> {
+        match error.kind() {
+            ErrorKind::NotFound => OriginalChange { key: key, kind: ChangeKind::Deleted },
+            _ => OriginalChange { key: key, kind: ChangeKind::Unavailable(error) },
+        }
+    }
+
+    /// Hash a file in fixed-size chunks, so large files are never held in memory all at once.
+    pub(super) fn hash_file(mut file: impl Read) -> io::Result<[u8; HASH_SIZE]> {
         use blake2::{*, digest::*};
         let mut hasher = VarBlake2b::new(HASH_SIZE).unwrap();
-        let mut buf = Vec::new();
-        file.read_to_end(&mut buf)?;
-        hasher.input(&buf);
+        let mut buf = vec![0; HASH_CHUNK_SIZE];
+        loop {
+            match file.read(&mut buf) {
+                Ok(0) => break,
+                Ok(read) => hasher.input(&buf[..read]),
+                Err(ref e) if e.kind() == ErrorKind::Interrupted => continue,
+                Err(e) => return Err(e),
+            }
+        }
         let mut hash = [0; HASH_SIZE];
         hasher.variable_result(|h| hash.copy_from_slice(h));
         Ok(hash)
@@ -52,7 +237,7 @@ impl<'a> DesktopBackgroundSource<'a> for FolderSource {
     fn name(&self) -> &str { &self.name }
 
     fn original(&self, key: &Self::Key) -> OriginalResult<&Self::Original> {
-        match self.originals.get(&key.filename) {
+        match self.originals.get(&key.path) {
             Some(original) => match !original.mismatch && original.hash == key.hash {
                 true => OriginalResult::Original(original),
                 false => OriginalResult::ContentMismatch(original),
@@ -62,72 +247,82 @@ impl<'a> DesktopBackgroundSource<'a> for FolderSource {
     }
 
     fn reload(&mut self) -> Vec<OriginalChange<FileKey, std::io::Error>> {
-        let mut contents: HashMap<_, _> = fs::read_dir(&self.folder).map(|dir| {
-            dir.filter_map(|r| r.ok())
-                .filter(|e| e.metadata().ok().map(|m| m.is_file()).unwrap_or(false))
-                .map(|e| (e.file_name(), e))
-                .collect()
-        }).unwrap_or_else(|_| HashMap::new());
-        
-        let mut to_remove = Vec::new();
+        self.rescan(false)
+    }
 
-        // Check all the originals we already have for changes.
-        let mut changes = self.originals.iter_mut().filter_map(|(filename, original)| {
-            contents.remove(filename);
-            let key = FileKey { filename: filename.clone(), hash: original.hash };
-            // TODO: maybe avoid computing the hash if there's a timestamp mismatch?
-            match File::open(&original.path).and_then(FolderSource::hash_file) {
-                Ok(hash) if hash != original.hash => {
-                    original.hash = hash; // TODO: Figure out how to deal with the two hashes
-                    Some(OriginalChange { key: key, kind: ChangeKind::Altered })
-                },
-                Err(ref e) if e.kind() == ErrorKind::NotFound => {
-                    to_remove.push(filename.clone());
-                    Some(OriginalChange { key: key, kind: ChangeKind::Deleted })
-                },
-                Err(e) => {
-                    Some(OriginalChange { key: key, kind: ChangeKind::Unavailable(e) })
-                },
-                _ => None,
-            }
-        }).collect::<Vec<_>>();
+    fn verify(&mut self) -> Vec<OriginalChange<FileKey, std::io::Error>> {
+        self.rescan(true)
+    }
 
-        self.originals.retain(|k, _| !to_remove.contains(k));
-        
-        // We've removed all existing originals, anything left in contents is new.
-        for (_, entry) in contents {
-            // TODO: We could go purely by extension here, and say that other files are corrupted
-            // instead of silently ignoring them. Alternatively, logging for people who care.
-            if image::open(entry.path()).is_ok() {
-                if let Ok(hash) = File::open(entry.path()).and_then(FolderSource::hash_file) {
-                    let filename = entry.file_name();
-                    self.originals.insert(filename.clone(), OriginalFile {
-                        mismatch: false,
-                        path: entry.path(),
-                        hash: hash,
-                    });
-                    changes.push(OriginalChange { 
-                        key: FileKey { filename, hash }, kind: ChangeKind::New
-                    });
-                }
+    fn root(&self) -> Option<&Path> { Some(&self.folder) }
+
+    fn plan_relocation(&self, root: PathBuf) -> RelocationPlanner<FileKey> {
+        let scan = self.scan.clone();
+        let originals = self.originals.iter().map(|(relative, original)| (relative.clone(), original.hash)).collect();
+        Box::new(move || FolderSource::match_relocation(&scan, &root, originals))
+    }
+
+    fn relocate(&mut self, root: PathBuf, plan: Relocation<FileKey>) -> Vec<OriginalChange<FileKey, std::io::Error>> {
+        let mut changes = Vec::new();
+        let mut relocated = HashMap::new();
+        for (old, new) in plan.matched {
+            // Originals which changed since the plan was made, or a file it matched twice, aren't moved.
+            if relocated.contains_key(&new.path) { continue }
+            if self.originals.get(&old.path).map_or(true, |original| original.hash != old.hash) { continue }
+            let mut original = self.originals.remove(&old.path).unwrap();
+            let path = root.join(&new.path);
+            original.stamp = fs::metadata(&path).map(FileStamp::of).ok();
+            original.path = path;
+            if new.path != old.path {
+                changes.push(OriginalChange { key: new.clone(), kind: ChangeKind::Moved { from: old } });
             }
+            relocated.insert(new.path, original);
         }
-
+        // Whatever the plan didn't move couldn't be found under the new root.
+        for (relative, original) in self.originals.drain() {
+            changes.push(OriginalChange { key: FileKey { path: relative, hash: original.hash }, kind: ChangeKind::Deleted });
+        }
+        self.originals = relocated;
+        self.folder = root;
         changes
     }
+
+    fn watching(&self) -> Option<bool> { Some(self.watch) }
+
+    fn set_watching(&mut self, watch: bool) { self.watch = watch; }
+
+    fn watch_paths(&self) -> Vec<(PathBuf, bool)> { vec![(self.folder.clone(), self.scan.recursive)] }
 }
 
 register_source_type!(FolderSource);
+register_source_migration!(FolderSource, 0, FolderSource::migrate_filename_keys);
+
+impl FolderSource {
+    /// Keys saved before recursive scanning only held a file name, under `filename`. A file name is
+    /// also its path relative to the folder, so these just need renaming.
+    fn migrate_filename_keys(saved: &mut serde_json::Value) {
+        let backgrounds = saved.get_mut("backgrounds").and_then(serde_json::Value::as_array_mut);
+        for background in backgrounds.into_iter().flatten() {
+            if let Some(key) = background.get_mut("key_data").and_then(serde_json::Value::as_object_mut) {
+                if let Some(filename) = key.remove("filename") {
+                    key.insert("path".to_owned(), filename);
+                }
+            }
+        }
+    }
+}
 
 #[derive(Hash, Clone, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
 pub struct FileKey {
-    filename: OsString,
-    hash: [u8; HASH_SIZE],
+    #[serde(with = "crate::utils::portable_path")]
+    pub(super) path: OsString, // Relative to the source folder, so files with the same name in different subfolders are distinct
+    #[serde(with = "crate::utils::compact_hash")]
+    pub(super) hash: [u8; HASH_SIZE],
 }
 
 impl CompareKey for FileKey {
     fn compare(&self, other: &Self) -> KeyRelation {
-        match (self.filename == other.filename, self.hash == other.hash) {
+        match (self.path == other.path, self.hash == other.hash) {
             (false, _) => KeyRelation::Distinct,
             (true, false) => KeyRelation::ContentMismatch,
             (true, true) => KeyRelation::SameOriginal,
@@ -135,11 +330,33 @@ impl CompareKey for FileKey {
     }
 }
 
-#[derive(Serialize, Deserialize)]
+#[derive(Clone, Serialize, Deserialize)]
 pub struct OriginalFile {
     mismatch: bool, // TODO: Remove this?
+    #[serde(with = "crate::utils::portable_path")]
     path: PathBuf,
-    hash: [u8; HASH_SIZE],
+    #[serde(with = "crate::utils::compact_hash")]
+    pub(super) hash: [u8; HASH_SIZE],
+    #[serde(default)]
+    stamp: Option<FileStamp>, // The file's metadata when `hash` was last computed
+}
+
+/// The size and modification time of a file, used to skip rehashing files that are unlikely to have changed.
+#[derive(Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub(super) struct FileStamp {
+    size: u64,
+    modified: Option<SystemTime>,
+}
+
+impl FileStamp {
+    pub(super) fn of(metadata: Metadata) -> FileStamp {
+        FileStamp { size: metadata.len(), modified: metadata.modified().ok() }
+    }
+
+    /// Filesystems that don't report modification times can't be trusted, so those stamps never match.
+    pub(super) fn matches(&self, other: &FileStamp) -> bool {
+        self.modified.is_some() && self == other
+    }
 }
 
 impl Original for OriginalFile {
@@ -154,4 +371,8 @@ impl Original for OriginalFile {
     fn location(&self) -> String {
         self.path.to_string_lossy().to_owned().to_string()
     }
+
+    fn boxed_clone(&self) -> Box<dyn Original + Send + Sync> {
+        Box::new(self.clone())
+    }
 }
\ No newline at end of file
@@ -0,0 +1,102 @@
+use std::{
+    collections::HashMap,
+    path::{Path, PathBuf},
+    ffi::OsString,
+    io,
+    fs,
+    rc::Rc,
+};
+
+use serde::{Serialize, Deserialize};
+
+use super::*;
+use super::folder::{FileKey, OriginalFile};
+
+/// A source whose originals are listed in a text file, one path per line. Blank lines and lines starting
+/// with `#` are ignored, so M3U-style playlists work as well. Relative paths are resolved against the
+/// folder containing the list.
+#[derive(Serialize, Deserialize)]
+pub struct ListSource {
+    #[serde(with = "crate::utils::portable_path")]
+    list: PathBuf,
+    name: String,
+    #[serde(with = "crate::utils::portable_path::pairs")]
+    originals: HashMap<OsString, OriginalFile>, // Keyed by each entry as it's written in the list
+}
+
+impl ListSource {
+    pub fn new(list: PathBuf, name: &str) -> Self {
+        ListSource {
+            list: list,
+            name: name.to_owned(),
+            originals: HashMap::new(),
+        }
+    }
+
+    /// Read the list, returning the resolved path of each entry keyed by the entry as it's written. Keys don't
+    /// depend on where the list is, so they stay valid if it's moved along with the files it lists.
+    fn read_list(&self) -> io::Result<HashMap<OsString, PathBuf>> {
+        let base = self.list.parent().unwrap_or(Path::new(""));
+        Ok(fs::read_to_string(&self.list)?.lines()
+            .map(str::trim)
+            .filter(|line| !line.is_empty() && !line.starts_with('#'))
+            .map(|line| (OsString::from(line), base.join(line)))
+            .collect())
+    }
+
+    fn rescan(&mut self, deep: bool) -> Vec<OriginalChange<FileKey, Rc<io::Error>>> {
+        let contents = match self.read_list() {
+            Ok(contents) => contents,
+            Err(e) => {
+                let error = Rc::new(e);
+                return self.originals.iter().map(|(path, original)| OriginalChange {
+                    key: FileKey { path: path.clone(), hash: original.hash },
+                    kind: ChangeKind::Unavailable(error.clone()),
+                }).collect()
+            }
+        };
+
+        // Entries that were taken off the list count as deleted, even if the file itself still exists.
+        let mut changes = Vec::new();
+        self.originals.retain(|path, original| {
+            if contents.contains_key(path) { return true }
+            changes.push(OriginalChange { key: FileKey { path: path.clone(), hash: original.hash }, kind: ChangeKind::Deleted });
+            false
+        });
+
+        changes.extend(FolderSource::diff_files(&mut self.originals, contents, deep).into_iter().map(|c| OriginalChange {
+            key: c.key, kind: c.kind.map_err(Rc::new)
+        }));
+        detect_moves(changes, |key| key.hash)
+    }
+}
+
+impl<'a> DesktopBackgroundSource<'a> for ListSource {
+    type Key = FileKey;
+    type Error = Rc<io::Error>;
+    type Original = OriginalFile;
+
+    const TYPE_IDENT: &'static str = "list";
+
+    fn name(&self) -> &str { &self.name }
+
+    fn original(&self, key: &Self::Key) -> OriginalResult<&Self::Original> {
+        match self.originals.get(&key.path) {
+            Some(original) => match original.hash == key.hash {
+                true => OriginalResult::Original(original),
+                false => OriginalResult::ContentMismatch(original),
+            },
+            None => OriginalResult::NotFound
+        }
+    }
+
+    fn reload(&mut self) -> Vec<OriginalChange<FileKey, Rc<io::Error>>> {
+        self.rescan(false)
+    }
+
+    fn verify(&mut self) -> Vec<OriginalChange<FileKey, Rc<io::Error>>> {
+        self.rescan(true)
+    }
+}
+
+register_source_type!(ListSource);
@@ -1,16 +1,26 @@
+use std::any::Any;
+use std::collections::HashMap;
 use std::hash::Hash;
 use std::fmt::Debug;
+use std::path::{Path, PathBuf};
 
 use crate::background::Original;
 
 mod folder;
+mod archive;
+mod list;
+mod command;
 mod erased;
 
 pub use erased::{OriginalKey, ErasedDesktopBackgroundSource, load_source_by_id, SourceLoadError, SourceLoader};
-pub use folder::FolderSource;
+pub use erased::{SourceMigration, source_version, migrate_source};
+pub use folder::{FolderSource, ScanOptions};
+pub use archive::{ArchiveSource, ArchiveFormat, remove_extracted_archives};
+pub use list::ListSource;
+pub use command::CommandSource;
 
 pub trait DesktopBackgroundSource<'a>: erased_serde::Serialize {
-    type Key: Hash + Clone + serde::Serialize + serde::de::DeserializeOwned + CompareKey;
+    type Key: Hash + Clone + serde::Serialize + serde::de::DeserializeOwned + CompareKey + Send + 'static;
     type Original: Original;
     type Error: Debug + 'a;
 
@@ -19,8 +29,43 @@ pub trait DesktopBackgroundSource<'a>: erased_serde::Serialize {
     fn name(&self) -> &str;
     fn original(&self, key: &Self::Key) -> OriginalResult<&Self::Original>;
     fn reload(&mut self) -> Vec<OriginalChange<Self::Key, Self::Error>>;
+
+    /// Like `reload`, but sources which normally skip checking originals that appear unchanged
+    /// (e.g. by comparing timestamps) should check every original thoroughly.
+    fn verify(&mut self) -> Vec<OriginalChange<Self::Key, Self::Error>> { self.reload() }
+
+    /// Work which the next `reload` or `verify` needs done first, and which can be done on another thread.
+    fn prepare_reload(&self) -> Option<ReloadPreparation> { None }
+    /// Give the source the result of its `prepare_reload` work, for the next `reload` or `verify` to use.
+    fn accept_prepared(&mut self, _prepared: Box<dyn Any + Send>) { }
+    /// Why the last `reload` or `verify` couldn't look for originals at all, if it couldn't. Problems are otherwise
+    /// reported through the originals they affect, and the source might not have any.
+    fn reload_error(&self) -> Option<String> { None }
+
+    /// The path this source's originals are found under, if it has one which can be changed with `relocate`.
+    fn root(&self) -> Option<&Path> { None }
+    /// Work out which originals could still be found if the source's root was changed, without changing anything.
+    /// Looking can be slow, so it's left to the returned planner, which can be run on another thread.
+    fn plan_relocation(&self, _root: PathBuf) -> RelocationPlanner<Self::Key> { Box::new(Relocation::default) }
+    /// Change the source's root, following a plan made by `plan_relocation`. Originals which the plan moved relative
+    /// to the root are reported as moved, and the rest are reported as deleted.
+    fn relocate(&mut self, _root: PathBuf, _plan: Relocation<Self::Key>) -> Vec<OriginalChange<Self::Key, Self::Error>> { Vec::new() }
+
+    /// Whether this source should be reloaded automatically when its files change on disk, or `None`
+    /// if it doesn't support watching.
+    fn watching(&self) -> Option<bool> { None }
+    fn set_watching(&mut self, _watch: bool) { }
+    /// The paths to watch for changes while watching is enabled, each paired with whether to watch it recursively.
+    fn watch_paths(&self) -> Vec<(PathBuf, bool)> { Vec::new() }
 }
 
+/// Work done ahead of a reload on another thread, so that something slow like running a command doesn't hold up the
+/// UI. What it returns is handed back to the source with `DesktopBackgroundSource::accept_prepared`.
+pub type ReloadPreparation = Box<dyn FnOnce() -> Box<dyn Any + Send> + Send>;
+
+/// Works out a `Relocation` for `DesktopBackgroundSource::plan_relocation`, possibly on another thread.
+pub type RelocationPlanner<K = OriginalKey> = Box<dyn FnOnce() -> Relocation<K> + Send>;
+
 #[derive(Copy, Clone, PartialEq, Eq)]
 pub enum KeyRelation { SameOriginal, ContentMismatch, Distinct }
 
@@ -30,7 +75,22 @@ pub trait CompareKey {
 
 pub struct OriginalChange<K = OriginalKey, E = Box<dyn Debug>> {
     pub key: K,
-    pub kind: ChangeKind<E>,
+    pub kind: ChangeKind<E, K>,
+}
+
+/// The outcome of moving a source to a new root, as predicted by `DesktopBackgroundSource::plan_relocation`.
+pub struct Relocation<K = OriginalKey> {
+    /// Originals which were found under the new root, as pairs of their old and new keys. No two originals are
+    /// matched to the same new key.
+    pub matched: Vec<(K, K)>,
+    /// Originals which couldn't be found under the new root, including any whose only match was taken by another.
+    pub unmatched: Vec<K>,
+}
+
+impl<K> Default for Relocation<K> {
+    fn default() -> Self {
+        Relocation { matched: Vec::new(), unmatched: Vec::new() }
+    }
 }
 
 pub enum OriginalResult<O> {
@@ -62,7 +122,7 @@ impl<O> OriginalResult<O> {
 }
 
 #[derive(Debug)]
-pub enum ChangeKind<E> {
+pub enum ChangeKind<E, K = OriginalKey> {
     /// A new original has been discovered.
     New,
     /// An existing original has been deleted.
@@ -70,5 +130,52 @@ pub enum ChangeKind<E> {
     /// An existing original has been altered.
     Altered,
     // An existing original has become unavailable (perhaps temporarily).
-    Unavailable(E)
+    Unavailable(E),
+    /// An existing original has been moved or renamed without its content changing. `from` is its old key.
+    Moved { from: K },
+}
+
+impl<E, K> ChangeKind<E, K> {
+    pub fn map_err<F>(self, f: impl FnOnce(E) -> F) -> ChangeKind<F, K> {
+        match self {
+            ChangeKind::New => ChangeKind::New,
+            ChangeKind::Deleted => ChangeKind::Deleted,
+            ChangeKind::Altered => ChangeKind::Altered,
+            ChangeKind::Unavailable(e) => ChangeKind::Unavailable(f(e)),
+            ChangeKind::Moved { from } => ChangeKind::Moved { from },
+        }
+    }
+
+    pub fn map_key<L>(self, f: impl FnOnce(K) -> L) -> ChangeKind<E, L> {
+        match self {
+            ChangeKind::New => ChangeKind::New,
+            ChangeKind::Deleted => ChangeKind::Deleted,
+            ChangeKind::Altered => ChangeKind::Altered,
+            ChangeKind::Unavailable(e) => ChangeKind::Unavailable(e),
+            ChangeKind::Moved { from } => ChangeKind::Moved { from: f(from) },
+        }
+    }
+}
+
+/// Report originals which were deleted and then rediscovered under a new key with the same content as moved,
+/// instead of as a deletion and an unrelated new original. `content` identifies the content of a key's original.
+fn detect_moves<K, E, C: Hash + Eq>(changes: Vec<OriginalChange<K, E>>, content: impl Fn(&K) -> C) -> Vec<OriginalChange<K, E>> {
+    let mut deleted: HashMap<C, Vec<K>> = HashMap::new();
+    let mut others = Vec::new();
+    for change in changes {
+        match change.kind {
+            ChangeKind::Deleted => deleted.entry(content(&change.key)).or_insert_with(Vec::new).push(change.key),
+            _ => others.push(change),
+        }
+    }
+
+    let mut changes = others.into_iter().map(|change| match change.kind {
+        ChangeKind::New => match deleted.get_mut(&content(&change.key)).and_then(Vec::pop) {
+            Some(from) => OriginalChange { key: change.key, kind: ChangeKind::Moved { from } },
+            None => change,
+        },
+        _ => change,
+    }).collect::<Vec<_>>();
+    changes.extend(deleted.into_iter().flat_map(|(_, keys)| keys).map(|key| OriginalChange { key, kind: ChangeKind::Deleted }));
+    changes
 }
\ No newline at end of file
@@ -1,4 +1,7 @@
 use std::ops::Deref;
+use std::path::Path;
+use std::fs::{self, File};
+use std::io::{self, Write, BufWriter};
 use winapi::um::{
     winnt::HRESULT,
     winuser::{GetSystemMetrics, SM_CXSCREEN, SM_CYSCREEN}
@@ -37,6 +40,27 @@ impl<T> Flatten<T> for Option<Option<T>> {
     }
 }
 
+/// Replace the file at `path` with whatever `write` writes, without ever leaving a partially written file there.
+/// The data is written to a temporary file in the same folder and synced to disk before being renamed over
+/// the original, so a crash or full disk leaves either the old contents or the new ones.
+pub fn write_atomically<E: From<io::Error>>(path: &Path, write: impl FnOnce(&mut dyn Write) -> Result<(), E>) -> Result<(), E> {
+    let mut temp_name = std::ffi::OsString::from(".");
+    temp_name.push(path.file_name().unwrap_or_default());
+    temp_name.push(".tmp");
+    let temp_path = path.with_file_name(temp_name);
+
+    let result = File::create(&temp_path).map_err(E::from).and_then(|file| {
+        let mut writer = BufWriter::new(file);
+        write(&mut writer)?;
+        writer.flush()?;
+        writer.get_ref().sync_all()?;
+        Ok(())
+    }).and_then(|_| fs::rename(&temp_path, path).map_err(E::from));
+
+    if result.is_err() { let _ = fs::remove_file(&temp_path); }
+    result
+}
+
 macro_rules! register_source_type {
     {$source:ty} => {
         ::inventory::submit! {
@@ -47,23 +71,287 @@ macro_rules! register_source_type {
     }
 }
 
-pub mod as_pairs {
+/// Register a function which upgrades saved sources of a type from `$from` to the next version.
+/// Migrations for a type must be registered for each version in turn, starting from 0.
+macro_rules! register_source_migration {
+    {$source:ty, $from:expr, $migration:expr} => {
+        ::inventory::submit! {
+            $crate::sources::SourceMigration(<$source>::TYPE_IDENT, $from, $migration)
+        }
+    }
+}
+
+/// Serializes paths and `OsString`s so that set files can be moved between platforms. Anything which is valid
+/// Unicode is saved as a string, and anything else as raw bytes (from Unix) or UTF-16 (from Windows). Relative
+/// paths are saved as a list of components, so they don't depend on the platform's separator, and absolute
+/// paths inside the folder given to `with_base` are saved relative to it.
+pub mod portable_path {
+    use std::cell::RefCell;
     use std::collections::HashMap;
+    use std::ffi::{OsStr, OsString};
     use std::hash::Hash;
+    use std::path::{Path, PathBuf};
     use serde::{Serialize, Serializer, Deserialize, Deserializer};
 
-    pub fn serialize<K, V, S>(map: &HashMap<K, V>, serializer: S) -> Result<S::Ok, S::Error> 
-        where K: Serialize, V: Serialize, S: Serializer
-    {
-        let pairs: Vec<(&K, &V)> = map.iter().collect();
-        Serialize::serialize(&pairs, serializer)
+    thread_local! {
+        static BASE: RefCell<Option<PathBuf>> = RefCell::new(None);
+    }
+
+    /// Run `f` with absolute paths inside `base` serialized relative to it, and relative paths deserialized
+    /// against it. With no base, paths are always serialized in full.
+    pub fn with_base<R>(base: Option<&Path>, f: impl FnOnce() -> R) -> R {
+        let old = BASE.with(|b| b.replace(base.map(Path::to_owned)));
+        let result = f();
+        BASE.with(|b| *b.borrow_mut() = old);
+        result
+    }
+
+    #[derive(Serialize, Deserialize)]
+    #[serde(untagged)]
+    enum EncodedOsStr {
+        Unicode(String),
+        Bytes { bytes: String }, // Base64
+        Wide { wide: Vec<u16> },
+        // Serde's own platform-specific representations, which older set files contain.
+        Unix { #[serde(rename = "Unix")] bytes: Vec<u8> },
+        Windows { #[serde(rename = "Windows")] wide: Vec<u16> },
+    }
+
+    #[derive(Serialize, Deserialize)]
+    #[serde(untagged)]
+    enum EncodedPath {
+        Whole(EncodedOsStr),
+        Components(Vec<EncodedOsStr>),
+        FromBase { from_base: Vec<EncodedOsStr> },
+    }
+
+    fn encode(s: &OsStr) -> EncodedOsStr {
+        match s.to_str() {
+            Some(s) => EncodedOsStr::Unicode(s.to_owned()),
+            None => encode_raw(s),
+        }
+    }
+
+    #[cfg(unix)]
+    fn encode_raw(s: &OsStr) -> EncodedOsStr {
+        use std::os::unix::ffi::OsStrExt;
+        EncodedOsStr::Bytes { bytes: base64::encode(s.as_bytes()) }
+    }
+
+    #[cfg(windows)]
+    fn encode_raw(s: &OsStr) -> EncodedOsStr {
+        use std::os::windows::ffi::OsStrExt;
+        EncodedOsStr::Wide { wide: s.encode_wide().collect() }
     }
 
-    pub fn deserialize<'de, K, V, D>(deserializer: D) -> Result<HashMap<K, V>, D::Error> 
-        where K: Deserialize<'de> + Hash + Eq, V: Deserialize<'de>, D: Deserializer<'de>
-    {
-        let pairs: Vec<(K, V)> = Deserialize::deserialize(deserializer)?;
-        Ok(pairs.into_iter().collect())
+    fn decode(s: EncodedOsStr) -> Result<OsString, base64::DecodeError> {
+        Ok(match s {
+            EncodedOsStr::Unicode(s) => s.into(),
+            EncodedOsStr::Bytes { bytes } => decode_bytes(base64::decode(&bytes)?),
+            EncodedOsStr::Unix { bytes } => decode_bytes(bytes),
+            EncodedOsStr::Wide { wide } | EncodedOsStr::Windows { wide } => decode_wide(wide),
+        })
+    }
+
+    #[cfg(unix)]
+    fn decode_bytes(bytes: Vec<u8>) -> OsString {
+        std::os::unix::ffi::OsStringExt::from_vec(bytes)
+    }
+
+    #[cfg(not(unix))]
+    fn decode_bytes(bytes: Vec<u8>) -> OsString {
+        String::from_utf8_lossy(&bytes).into_owned().into()
+    }
+
+    #[cfg(windows)]
+    fn decode_wide(wide: Vec<u16>) -> OsString {
+        std::os::windows::ffi::OsStringExt::from_wide(&wide)
+    }
+
+    #[cfg(not(windows))]
+    fn decode_wide(wide: Vec<u16>) -> OsString {
+        String::from_utf16_lossy(&wide).into()
+    }
+
+    fn components(path: &Path) -> Vec<EncodedOsStr> {
+        path.components().map(|c| encode(c.as_os_str())).collect()
+    }
+
+    fn encode_path(path: &Path) -> EncodedPath {
+        if path.is_relative() {
+            // A lone file name doesn't depend on the separator, so there's no need to split it up.
+            return match path.components().count() {
+                0 | 1 => EncodedPath::Whole(encode(path.as_os_str())),
+                _ => EncodedPath::Components(components(path)),
+            }
+        }
+        let relative = BASE.with(|b| b.borrow().as_ref().and_then(|base| path.strip_prefix(base).ok()).map(components));
+        match relative {
+            Some(from_base) => EncodedPath::FromBase { from_base },
+            None => EncodedPath::Whole(encode(path.as_os_str())),
+        }
+    }
+
+    fn decode_path(path: EncodedPath) -> Result<OsString, base64::DecodeError> {
+        let join = |c: Vec<EncodedOsStr>| c.into_iter().map(decode).collect::<Result<PathBuf, _>>();
+        Ok(match path {
+            EncodedPath::Whole(s) => decode(s)?,
+            EncodedPath::Components(c) => join(c)?.into_os_string(),
+            EncodedPath::FromBase { from_base } => {
+                let relative = join(from_base)?;
+                BASE.with(|b| b.borrow().as_ref().map(|base| base.join(&relative))).unwrap_or(relative).into_os_string()
+            }
+        })
+    }
+
+    pub fn serialize<P: AsRef<OsStr>, S: Serializer>(path: &P, serializer: S) -> Result<S::Ok, S::Error> {
+        encode_path(Path::new(path)).serialize(serializer)
+    }
+
+    pub fn deserialize<'de, P: From<OsString>, D: Deserializer<'de>>(deserializer: D) -> Result<P, D::Error> {
+        let path = decode_path(EncodedPath::deserialize(deserializer)?).map_err(serde::de::Error::custom)?;
+        Ok(P::from(path))
+    }
+
+    pub mod option {
+        use super::*;
+
+        pub fn serialize<P: AsRef<OsStr>, S: Serializer>(path: &Option<P>, serializer: S) -> Result<S::Ok, S::Error> {
+            path.as_ref().map(|p| encode_path(Path::new(p))).serialize(serializer)
+        }
+
+        pub fn deserialize<'de, P: From<OsString>, D: Deserializer<'de>>(deserializer: D) -> Result<Option<P>, D::Error> {
+            match Option::<EncodedPath>::deserialize(deserializer)? {
+                Some(path) => decode_path(path).map(|p| Some(P::from(p))).map_err(serde::de::Error::custom),
+                None => Ok(None),
+            }
+        }
+    }
+
+    /// For maps keyed by paths. Paths can't be used as JSON keys, so these are saved as a list of pairs, sorted so
+    /// that saving the same map twice gives the same result.
+    pub mod pairs {
+        use super::*;
+
+        pub fn serialize<K, V, S>(map: &HashMap<K, V>, serializer: S) -> Result<S::Ok, S::Error> 
+            where K: AsRef<OsStr>, V: Serialize, S: Serializer
+        {
+            let mut sorted: Vec<(&K, &V)> = map.iter().collect();
+            sorted.sort_by(|(k1, _), (k2, _)| k1.as_ref().cmp(k2.as_ref()));
+            let pairs: Vec<(EncodedPath, &V)> = sorted.into_iter().map(|(k, v)| (encode_path(Path::new(k)), v)).collect();
+            Serialize::serialize(&pairs, serializer)
+        }
+
+        pub fn deserialize<'de, K, V, D>(deserializer: D) -> Result<HashMap<K, V>, D::Error> 
+            where K: From<OsString> + Hash + Eq, V: Deserialize<'de>, D: Deserializer<'de>
+        {
+            let pairs: Vec<(EncodedPath, V)> = Deserialize::deserialize(deserializer)?;
+            pairs.into_iter().map(|(k, v)| Ok((K::from(decode_path(k).map_err(serde::de::Error::custom)?), v))).collect()
+        }
     }
 }
 
+
+/// Serialization for content hashes. Human-readable formats get a base64 string, which is far more compact than serde's
+/// list of numbers, and binary formats get raw bytes. Older set files with the list are still read.
+pub mod compact_hash {
+    use std::fmt;
+    use serde::{Serializer, Deserializer, de};
+
+    pub fn serialize<H: AsRef<[u8]>, S: Serializer>(hash: &H, serializer: S) -> Result<S::Ok, S::Error> {
+        match serializer.is_human_readable() {
+            true => serializer.serialize_str(&base64::encode(hash.as_ref())),
+            false => serializer.serialize_bytes(hash.as_ref()),
+        }
+    }
+
+    pub fn deserialize<'de, H: Default + AsMut<[u8]>, D: Deserializer<'de>>(deserializer: D) -> Result<H, D::Error> {
+        deserializer.deserialize_any(HashVisitor(std::marker::PhantomData))
+    }
+
+    struct HashVisitor<H>(std::marker::PhantomData<H>);
+
+    impl<H: Default + AsMut<[u8]>> HashVisitor<H> {
+        fn from_bytes<E: de::Error>(bytes: &[u8]) -> Result<H, E> {
+            let mut hash = H::default();
+            if bytes.len() != hash.as_mut().len() { return Err(E::invalid_length(bytes.len(), &"a hash of the right size")) }
+            hash.as_mut().copy_from_slice(bytes);
+            Ok(hash)
+        }
+    }
+
+    impl<'de, H: Default + AsMut<[u8]>> de::Visitor<'de> for HashVisitor<H> {
+        type Value = H;
+
+        fn expecting(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
+            formatter.write_str("a hash, as a base64 string, bytes, or a list of numbers")
+        }
+
+        fn visit_str<E: de::Error>(self, value: &str) -> Result<H, E> {
+            Self::from_bytes(&base64::decode(value).map_err(E::custom)?)
+        }
+
+        fn visit_bytes<E: de::Error>(self, value: &[u8]) -> Result<H, E> {
+            Self::from_bytes(value)
+        }
+
+        fn visit_seq<A: de::SeqAccess<'de>>(self, mut seq: A) -> Result<H, A::Error> {
+            let mut bytes = Vec::new();
+            while let Some(byte) = seq.next_element::<u8>()? { bytes.push(byte); }
+            Self::from_bytes(&bytes)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::PathBuf;
+    use serde::{Serialize, Deserialize};
+    use serde_json::json;
+    use super::{portable_path, compact_hash};
+
+    #[test]
+    fn portable_paths_round_trip() {
+        for path in &["/walls/a.png", "walls/a.png", "a.png"] {
+            let encoded = portable_path::serialize(&PathBuf::from(path), serde_json::value::Serializer).unwrap();
+            let decoded: PathBuf = portable_path::deserialize(encoded).unwrap();
+            assert_eq!(decoded, PathBuf::from(path));
+        }
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn non_unicode_paths_round_trip() {
+        use std::os::unix::ffi::OsStringExt;
+        let path = PathBuf::from(std::ffi::OsString::from_vec(vec![b'a', 0xff, b'.', b'p', b'n', b'g']));
+        let encoded = portable_path::serialize(&path, serde_json::value::Serializer).unwrap();
+        assert!(encoded["bytes"].is_string());
+        let decoded: PathBuf = portable_path::deserialize(encoded).unwrap();
+        assert_eq!(decoded, path);
+    }
+
+    #[test]
+    fn legacy_os_strings_decode() {
+        let decoded: PathBuf = portable_path::deserialize(json!({ "Unix": b"a.png" })).unwrap();
+        assert_eq!(decoded, PathBuf::from("a.png"));
+        let decoded: PathBuf = portable_path::deserialize(json!({ "Windows": "a.png".encode_utf16().collect::<Vec<_>>() })).unwrap();
+        assert_eq!(decoded, PathBuf::from("a.png"));
+    }
+
+    #[derive(Serialize, Deserialize, PartialEq, Debug)]
+    struct Hashed(#[serde(with = "compact_hash")] [u8; 32]);
+
+    #[test]
+    fn hashes_decode_from_every_encoding() {
+        let hashed = Hashed([7; 32]);
+        let json = serde_json::to_value(&hashed).unwrap();
+        assert!(json.is_string());
+        assert_eq!(serde_json::from_value::<Hashed>(json).unwrap(), hashed);
+        let cbor = serde_cbor::to_vec(&hashed).unwrap();
+        assert_eq!(cbor.len(), 34); // A byte string's header, and the bytes
+        assert_eq!(serde_cbor::from_slice::<Hashed>(&cbor).unwrap(), hashed);
+        // Older set files have serde's list of numbers.
+        assert_eq!(serde_json::from_value::<Hashed>(json!(vec![7; 32])).unwrap(), hashed);
+        assert!(serde_json::from_value::<Hashed>(json!(vec![7; 31])).is_err());
+    }
+}